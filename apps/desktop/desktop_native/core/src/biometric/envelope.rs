@@ -0,0 +1,333 @@
+//! Storage format of biometric secrets.
+//!
+//! Secrets are stored as `v<version>.<kdf>.<cipher string>`, e.g. `v2.hkdf-sha256.2.<iv>|<data>|<mac>`.
+//! Entries written by older clients have no envelope and are still accepted, so they can be
+//! upgraded on read:
+//!
+//! - The unencrypted secret, written by early Windows clients.
+//! - A bare type 0 cipher string, encrypted with `Sha256(os_key_part|client_key_part)`.
+
+use std::str::FromStr;
+
+use aes::cipher::generic_array::GenericArray;
+use anyhow::{anyhow, bail, Result};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use super::KeyMaterial;
+use crate::{
+    crypto::{self, CipherString},
+    secret::SecretBuf,
+};
+
+pub const CURRENT_VERSION: u32 = 2;
+
+// Binds derived keys to their purpose, so the same key material never yields the same key in
+// another context
+const HKDF_INFO: &[u8] = b"bitwarden-desktop-biometric-secret-v2";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    /// `Sha256(os_key_part|client_key_part)`, used by version 1.
+    Sha256,
+    /// HKDF-SHA256 expanded with [HKDF_INFO] into an encryption and a mac key, used by version 2.
+    HkdfSha256,
+}
+
+impl Kdf {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kdf::Sha256 => "sha256",
+            Kdf::HkdfSha256 => "hkdf-sha256",
+        }
+    }
+}
+
+impl FromStr for Kdf {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(Kdf::Sha256),
+            "hkdf-sha256" => Ok(Kdf::HkdfSha256),
+            _ => Err(anyhow!("Unknown biometric key derivation: {}", s)),
+        }
+    }
+}
+
+/// Encryption and mac key of the current envelope version.
+pub struct EnvelopeKey {
+    enc: [u8; 32],
+    mac: [u8; 32],
+}
+
+impl EnvelopeKey {
+    pub fn derive(key_material: &KeyMaterial) -> Result<Self> {
+        let hkdf = Hkdf::<Sha256>::new(None, key_material.digest_material().as_bytes());
+        let mut okm = [0u8; 64];
+        hkdf.expand(HKDF_INFO, &mut okm)
+            .map_err(|e| anyhow!("Failed to derive biometric key: {}", e))?;
+        Self::from_bytes(&okm)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.enc, self.mac].concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 64 {
+            bail!("Expected length {}, got {}", 64, bytes.len());
+        }
+        Ok(EnvelopeKey {
+            enc: bytes[..32].try_into()?,
+            mac: bytes[32..].try_into()?,
+        })
+    }
+}
+
+/// A secret as read from the password store.
+#[derive(Debug)]
+pub enum StoredSecret {
+    Plain(String),
+    Encrypted {
+        version: u32,
+        kdf: Kdf,
+        secret: CipherString,
+    },
+}
+
+impl FromStr for StoredSecret {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((version, rest)) = s.strip_prefix('v').and_then(|s| s.split_once('.')) {
+            if let Ok(version) = version.parse::<u32>() {
+                let (kdf, secret) = rest
+                    .split_once('.')
+                    .ok_or(anyhow!("Invalid biometric secret envelope"))?;
+                if version > CURRENT_VERSION {
+                    bail!(
+                        "Biometric secret version {} is not supported, expected at most {}",
+                        version,
+                        CURRENT_VERSION
+                    );
+                }
+                return Ok(StoredSecret::Encrypted {
+                    version,
+                    kdf: kdf.parse()?,
+                    secret: secret.parse()?,
+                });
+            }
+        }
+
+        match CipherString::from_str(s) {
+            Ok(secret @ CipherString::AesCbc256_B64 { .. }) => Ok(StoredSecret::Encrypted {
+                version: 1,
+                kdf: Kdf::Sha256,
+                secret,
+            }),
+            Ok(secret) => Err(anyhow!("Unsupported biometric secret {:?}", secret)),
+            // A damaged cipher string must not be mistaken for a plain secret, the upgrade would
+            // overwrite the entry with it
+            Err(e) if has_cipher_prefix(s) => Err(anyhow!("Invalid biometric secret: {}", e)),
+            // Anything else was stored without encryption
+            Err(_) => Ok(StoredSecret::Plain(s.to_owned())),
+        }
+    }
+}
+
+// Whether `s` starts like a cipher string, e.g. `0.` or `2.`
+fn has_cipher_prefix(s: &str) -> bool {
+    s.split_once('.').is_some_and(|(enc_type, _)| {
+        !enc_type.is_empty() && enc_type.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+impl StoredSecret {
+    /// Whether the entry should be rewritten with [seal].
+    pub fn needs_upgrade(&self) -> bool {
+        !matches!(self, StoredSecret::Encrypted { version, .. } if *version == CURRENT_VERSION)
+    }
+
+    /// Decrypt the secret with `key`. Version 1 entries are decrypted with `key_material` instead,
+    /// as their key cannot be derived from `key`.
+    pub fn decrypt(
+        &self,
+        key: &EnvelopeKey,
+        key_material: Option<&KeyMaterial>,
+    ) -> Result<SecretBuf> {
+        let decrypted = match self {
+            StoredSecret::Plain(secret) => return Ok(SecretBuf::from_slice(secret.as_bytes())?),
+            StoredSecret::Encrypted {
+                version: 1,
+                kdf: Kdf::Sha256,
+                secret: CipherString::AesCbc256_B64 { iv, data },
+            } => {
+                let key_material = key_material.ok_or(anyhow!(
+                    "Key material is required to upgrade the biometric secret"
+                ))?;
+                crypto::decrypt_aes256(iv, data, key_material.derive_key()?)?
+            }
+            StoredSecret::Encrypted {
+                version: 2,
+                kdf: Kdf::HkdfSha256,
+                secret: CipherString::AesCbc256_HmacSha256_B64 { iv, mac, data },
+            } => crypto::decrypt_aes256_hmac(
+                iv,
+                mac,
+                data,
+                GenericArray::clone_from_slice(&key.mac),
+                GenericArray::clone_from_slice(&key.enc),
+            )?,
+            StoredSecret::Encrypted { version, kdf, .. } => {
+                bail!(
+                    "Invalid biometric secret version {} using {}",
+                    version,
+                    kdf.as_str()
+                )
+            }
+        };
+
+        // Callers expect a string, so reject anything that would fail later on
+        decrypted.as_str()?;
+        Ok(decrypted)
+    }
+}
+
+/// Encrypt `secret` in the current envelope version.
+pub fn seal(secret: &str, key: &EnvelopeKey, iv: [u8; 16]) -> Result<String> {
+    let encrypted = crypto::encrypt_aes256_hmac(
+        secret.as_bytes(),
+        iv,
+        GenericArray::clone_from_slice(&key.mac),
+        GenericArray::clone_from_slice(&key.enc),
+    )?;
+
+    Ok(format!(
+        "v{}.{}.{}",
+        CURRENT_VERSION,
+        Kdf::HkdfSha256.as_str(),
+        encrypted
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Output of the type 0 encryption of "secret" with `key_material()`
+    const LEGACY_SECRET: &str = "0.l9fhDUP/wDJcKwmEzcb/3w==|uP4LcqoCCj5FxBDP77NV6Q==";
+
+    fn key_material() -> KeyMaterial {
+        KeyMaterial {
+            os_key_part_b64: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned(),
+            client_key_part_b64: Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned()),
+        }
+    }
+
+    fn key() -> EnvelopeKey {
+        EnvelopeKey::derive(&key_material()).unwrap()
+    }
+
+    #[test]
+    fn seal_and_decrypt() {
+        let sealed = seal("secret", &key(), [1; 16]).unwrap();
+        assert!(sealed.starts_with("v2.hkdf-sha256.2."));
+
+        let stored: StoredSecret = sealed.parse().unwrap();
+        assert!(!stored.needs_upgrade());
+        assert_eq!(stored.decrypt(&key(), None).unwrap(), "secret");
+    }
+
+    #[test]
+    fn decrypt_fails_with_wrong_key() {
+        let sealed = seal("secret", &key(), [1; 16]).unwrap();
+        let other = EnvelopeKey::derive(&KeyMaterial {
+            os_key_part_b64: "AQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned(),
+            client_key_part_b64: None,
+        })
+        .unwrap();
+
+        let stored: StoredSecret = sealed.parse().unwrap();
+        assert!(stored.decrypt(&other, None).is_err());
+    }
+
+    #[test]
+    fn derive_uses_context_label() {
+        // A key without the label is the plain HKDF output of the same input
+        let hkdf = Hkdf::<Sha256>::new(None, key_material().digest_material().as_bytes());
+        let mut unlabeled = [0u8; 64];
+        hkdf.expand(&[], &mut unlabeled).unwrap();
+
+        assert_ne!(key().to_bytes(), unlabeled.to_vec());
+        assert_eq!(key().to_bytes(), key().to_bytes());
+    }
+
+    #[test]
+    fn key_bytes_roundtrip() {
+        let bytes = key().to_bytes();
+        assert_eq!(EnvelopeKey::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+        assert!(EnvelopeKey::from_bytes(&bytes[..32]).is_err());
+    }
+
+    #[test]
+    fn legacy_plain_secret() {
+        let stored: StoredSecret = "password".parse().unwrap();
+        assert!(stored.needs_upgrade());
+        assert_eq!(stored.decrypt(&key(), None).unwrap(), "password");
+    }
+
+    #[test]
+    fn rejects_malformed_cipher_strings() {
+        for stored in ["0.l9fhDUP/wDJcKwmEzcb/3w==", "2.AAAA|BBBB", "0.not base64|!!"] {
+            assert!(stored.parse::<StoredSecret>().is_err(), "{}", stored);
+        }
+    }
+
+    #[test]
+    fn legacy_type_0_secret() {
+        let stored: StoredSecret = LEGACY_SECRET.parse().unwrap();
+        assert!(matches!(
+            stored,
+            StoredSecret::Encrypted {
+                version: 1,
+                kdf: Kdf::Sha256,
+                ..
+            }
+        ));
+        assert!(stored.needs_upgrade());
+        assert_eq!(
+            stored.decrypt(&key(), Some(&key_material())).unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn legacy_type_0_secret_requires_key_material() {
+        let stored: StoredSecret = LEGACY_SECRET.parse().unwrap();
+        assert!(stored.decrypt(&key(), None).is_err());
+    }
+
+    #[test]
+    fn versioned_type_0_secret() {
+        let stored: StoredSecret = format!("v1.sha256.{}", LEGACY_SECRET).parse().unwrap();
+        assert!(stored.needs_upgrade());
+        assert_eq!(
+            stored.decrypt(&key(), Some(&key_material())).unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        assert!(format!("v3.hkdf-sha256.{}", LEGACY_SECRET)
+            .parse::<StoredSecret>()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_mismatched_kdf() {
+        let stored: StoredSecret = format!("v2.sha256.{}", LEGACY_SECRET).parse().unwrap();
+        assert!(stored.decrypt(&key(), Some(&key_material())).is_err());
+    }
+}
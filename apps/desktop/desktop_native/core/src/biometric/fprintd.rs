@@ -0,0 +1,384 @@
+use anyhow::{anyhow, Result};
+use tokio::sync::{mpsc::Sender, Notify};
+use zbus::{
+    export::futures_util::TryStreamExt, proxy::CacheProperties, zvariant::OwnedObjectPath,
+    Connection, MatchRule, MessageStream,
+};
+
+use super::{VerifyProgress, VerifyStatus};
+
+const DEVICE_INTERFACE: &str = "net.reactivated.Fprint.Device";
+
+#[zbus::proxy(
+    interface = "net.reactivated.Fprint.Manager",
+    default_service = "net.reactivated.Fprint",
+    default_path = "/net/reactivated/Fprint/Manager"
+)]
+trait Manager {
+    fn get_default_device(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[zbus::proxy(
+    interface = "net.reactivated.Fprint.Device",
+    default_service = "net.reactivated.Fprint"
+)]
+trait Device {
+    fn claim(&self, username: &str) -> zbus::Result<()>;
+    fn release(&self) -> zbus::Result<()>;
+    fn list_enrolled_fingers(&self, username: &str) -> zbus::Result<Vec<String>>;
+    fn verify_start(&self, finger_name: &str) -> zbus::Result<()>;
+    fn verify_stop(&self) -> zbus::Result<()>;
+}
+
+/// Check if the default fingerprint reader has fingers enrolled for the current user.
+pub async fn has_enrolled_fingers(connection: &Connection) -> bool {
+    match default_device(connection).await {
+        // An empty user name refers to the user owning the calling process
+        Ok(device) => matches!(device.list_enrolled_fingers("").await, Ok(f) if !f.is_empty()),
+        Err(_) => false,
+    }
+}
+
+/// Verify any enrolled finger of the current user on the default fingerprint reader.
+///
+/// fprintd has no dialog, so `message` is sent to `progress` along with [VerifyStatus::Waiting]
+/// once the reader is ready and with intermediate results such as "swipe again". Resolves to
+/// `false` if the finger did not match or `cancel` was notified.
+pub async fn verify(
+    connection: &Connection,
+    message: &str,
+    cancel: &Notify,
+    progress: Option<Sender<VerifyProgress>>,
+) -> Result<bool> {
+    let device = default_device(connection).await?;
+    device.claim("").await?;
+    let mut claim = Claim {
+        device: Some(device),
+        verifying: false,
+    };
+
+    let result = verify_claimed(connection, &mut claim, message, cancel, progress).await;
+
+    // Release the device regardless of the result, so other applications can use it
+    let released = claim.release().await;
+    let matched = result?;
+    released?;
+    Ok(matched)
+}
+
+async fn verify_claimed(
+    connection: &Connection,
+    claim: &mut Claim,
+    message: &str,
+    cancel: &Notify,
+    progress: Option<Sender<VerifyProgress>>,
+) -> Result<bool> {
+    let device = claim.device.as_ref().unwrap();
+    let rule = MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .interface(DEVICE_INTERFACE)?
+        .member("VerifyStatus")?
+        .path(device.inner().path().to_owned())?
+        .build();
+    let mut stream = MessageStream::for_match_rule(rule, connection, None).await?;
+
+    device.verify_start("any").await?;
+    claim.verifying = true;
+
+    let report = |status| {
+        if let Some(progress) = &progress {
+            // Progress is informational, a closed or full receiver must not fail the verification
+            let _ = progress.try_send(VerifyProgress {
+                status,
+                message: message.to_owned(),
+            });
+        }
+    };
+    report(VerifyStatus::Waiting);
+
+    loop {
+        let signal = tokio::select! {
+            signal = stream.try_next() => signal?,
+            _ = cancel.notified() => return Ok(false),
+        };
+        let Some(signal) = signal else {
+            return Err(anyhow!("Connection to fprintd closed"));
+        };
+
+        let (result, done): (String, bool) = signal.body().deserialize()?;
+        let status = VerifyStatus::from_fprintd(&result);
+        report(status);
+
+        if done {
+            return Ok(status == VerifyStatus::Match);
+        }
+    }
+}
+
+/// A claimed device, stopping a started verification and releasing the device when the prompt
+/// ends. If the prompt future is dropped before [Claim::release] this happens in a spawned task.
+struct Claim {
+    device: Option<DeviceProxy<'static>>,
+    verifying: bool,
+}
+
+impl Claim {
+    async fn release(mut self) -> Result<()> {
+        let device = self.device.take().unwrap();
+        let stopped = if self.verifying {
+            device.verify_stop().await
+        } else {
+            Ok(())
+        };
+        let released = device.release().await;
+        stopped?;
+        released?;
+        Ok(())
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let Some(device) = self.device.take() else {
+            return;
+        };
+        let verifying = self.verifying;
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                if verifying {
+                    let _ = device.verify_stop().await;
+                }
+                let _ = device.release().await;
+            });
+        }
+    }
+}
+
+async fn default_device(connection: &Connection) -> Result<DeviceProxy<'static>> {
+    let manager = ManagerProxy::builder(connection)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+    let path = manager.get_default_device().await?;
+
+    Ok(DeviceProxy::builder(connection)
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?)
+}
+
+impl VerifyStatus {
+    fn from_fprintd(result: &str) -> Self {
+        match result {
+            "verify-match" => VerifyStatus::Match,
+            "verify-no-match" => VerifyStatus::NoMatch,
+            "verify-retry-scan" => VerifyStatus::RetryScan,
+            "verify-swipe-too-short" => VerifyStatus::SwipeTooShort,
+            "verify-finger-not-centered" => VerifyStatus::FingerNotCentered,
+            "verify-remove-and-retry" => VerifyStatus::RemoveAndRetry,
+            "verify-disconnected" => VerifyStatus::Disconnected,
+            _ => VerifyStatus::UnknownError,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use zbus::SignalContext;
+
+    use super::*;
+
+    const MANAGER_PATH: &str = "/net/reactivated/Fprint/Manager";
+    const DEVICE_PATH: &str = "/net/reactivated/Fprint/Device/0";
+
+    #[derive(zbus::DBusError, Debug)]
+    #[zbus(prefix = "net.reactivated.Fprint.Error")]
+    enum FprintError {
+        #[zbus(error)]
+        ZBus(zbus::Error),
+        NoEnrolledPrints(String),
+    }
+
+    struct MockManager;
+
+    #[zbus::interface(name = "net.reactivated.Fprint.Manager")]
+    impl MockManager {
+        fn get_default_device(&self) -> OwnedObjectPath {
+            OwnedObjectPath::try_from(DEVICE_PATH).unwrap()
+        }
+    }
+
+    #[derive(Default)]
+    struct MockDevice {
+        enrolled: Vec<String>,
+        // VerifyStatus signals emitted after VerifyStart
+        statuses: Vec<(&'static str, bool)>,
+        // Emit a VerifyStatus signal with an unexpected body after VerifyStart
+        malformed_status: bool,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::interface(name = "net.reactivated.Fprint.Device")]
+    impl MockDevice {
+        fn claim(&self, _username: String) {
+            self.calls.lock().unwrap().push("Claim".to_owned());
+        }
+
+        fn release(&self) {
+            self.calls.lock().unwrap().push("Release".to_owned());
+        }
+
+        fn list_enrolled_fingers(&self, _username: String) -> Result<Vec<String>, FprintError> {
+            if self.enrolled.is_empty() {
+                return Err(FprintError::NoEnrolledPrints("No prints enrolled".to_owned()));
+            }
+            Ok(self.enrolled.clone())
+        }
+
+        async fn verify_start(
+            &self,
+            finger_name: String,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> zbus::fdo::Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("VerifyStart({})", finger_name));
+            for (result, done) in &self.statuses {
+                Self::verify_status(&ctxt, result, *done).await?;
+            }
+            if self.malformed_status {
+                Self::malformed_verify_status(&ctxt, 1).await?;
+            }
+            Ok(())
+        }
+
+        fn verify_stop(&self) {
+            self.calls.lock().unwrap().push("VerifyStop".to_owned());
+        }
+
+        #[zbus(signal)]
+        async fn verify_status(
+            ctxt: &SignalContext<'_>,
+            result: &str,
+            done: bool,
+        ) -> zbus::Result<()>;
+
+        #[zbus(signal, name = "VerifyStatus")]
+        async fn malformed_verify_status(ctxt: &SignalContext<'_>, result: u32) -> zbus::Result<()>;
+    }
+
+    async fn connect(device: MockDevice) -> (Connection, Connection) {
+        crate::dbus_mock::connect(|b| {
+            b.serve_at(MANAGER_PATH, MockManager)?
+                .serve_at(DEVICE_PATH, device)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn has_enrolled_fingers_with_fingers() {
+        let device = MockDevice {
+            enrolled: vec!["right-index-finger".to_owned()],
+            ..Default::default()
+        };
+        let (client, _server) = connect(device).await;
+
+        assert!(has_enrolled_fingers(&client).await);
+    }
+
+    #[tokio::test]
+    async fn has_enrolled_fingers_without_fingers() {
+        let (client, _server) = connect(MockDevice::default()).await;
+
+        assert!(!has_enrolled_fingers(&client).await);
+    }
+
+    #[tokio::test]
+    async fn verify_reports_progress_and_matches() {
+        let device = MockDevice {
+            statuses: vec![
+                ("verify-swipe-too-short", false),
+                ("verify-retry-scan", false),
+                ("verify-match", true),
+            ],
+            ..Default::default()
+        };
+        let calls = device.calls.clone();
+        let (client, _server) = connect(device).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let result = verify(&client, "Unlock your vault", &Notify::new(), Some(tx)).await;
+        assert!(result.unwrap());
+
+        let mut progress = Vec::new();
+        while let Some(update) = rx.recv().await {
+            assert_eq!(update.message, "Unlock your vault");
+            progress.push(update.status);
+        }
+        assert_eq!(
+            progress,
+            vec![
+                VerifyStatus::Waiting,
+                VerifyStatus::SwipeTooShort,
+                VerifyStatus::RetryScan,
+                VerifyStatus::Match
+            ]
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["Claim", "VerifyStart(any)", "VerifyStop", "Release"]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_no_match() {
+        let device = MockDevice {
+            statuses: vec![("verify-no-match", true)],
+            ..Default::default()
+        };
+        let calls = device.calls.clone();
+        let (client, _server) = connect(device).await;
+
+        let result = verify(&client, "", &Notify::new(), None).await;
+        assert!(!result.unwrap());
+        assert_eq!(calls.lock().unwrap().last().unwrap(), "Release");
+    }
+
+    #[tokio::test]
+    async fn verify_cancelled_releases_device() {
+        // No statuses, so the verification only ends when cancelled
+        let device = MockDevice::default();
+        let calls = device.calls.clone();
+        let (client, _server) = connect(device).await;
+
+        let cancel = Notify::new();
+        cancel.notify_one();
+        let result = verify(&client, "", &cancel, None).await;
+        assert!(!result.unwrap());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["Claim", "VerifyStart(any)", "VerifyStop", "Release"]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_failure_stops_and_releases_device() {
+        let device = MockDevice {
+            malformed_status: true,
+            ..Default::default()
+        };
+        let calls = device.calls.clone();
+        let (client, _server) = connect(device).await;
+
+        let result = verify(&client, "", &Notify::new(), None).await;
+        assert!(result.is_err());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["Claim", "VerifyStart(any)", "VerifyStop", "Release"]
+        );
+    }
+}
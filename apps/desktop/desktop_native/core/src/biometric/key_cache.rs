@@ -0,0 +1,423 @@
+use std::{ffi::CString, sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, Result};
+use libc::{c_long, KEYCTL_CLEAR, KEYCTL_READ, KEYCTL_REVOKE, KEYCTL_SEARCH, KEYCTL_SETPERM,
+    KEYCTL_SET_TIMEOUT, KEY_SPEC_SESSION_KEYRING};
+
+// Permissions granted to processes possessing the key, i.e. processes that can reach it through
+// their session keyring. Users, groups and others get no access.
+// https://github.com/torvalds/linux/blob/a38297e3fb012ddfa7ce0321a7e5a8daeb1872b6/include/linux/key.h#L41
+const KEY_POS_VIEW: u32 = 0x01000000;
+const KEY_POS_READ: u32 = 0x02000000;
+const KEY_POS_WRITE: u32 = 0x04000000;
+const KEY_POS_SEARCH: u32 = 0x08000000;
+const KEY_POS_SETATTR: u32 = 0x20000000;
+const POSSESSOR_PERMISSIONS: u32 =
+    KEY_POS_VIEW | KEY_POS_READ | KEY_POS_WRITE | KEY_POS_SEARCH | KEY_POS_SETATTR;
+
+const KEYRING_NAME: &str = "bitwarden_biometric";
+
+static TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Cache derived biometric keys in the session keyring for `timeout`. `None` disables the cache
+/// and revokes all cached keys.
+pub fn set_timeout(timeout: Option<Duration>) -> Result<()> {
+    *TIMEOUT.lock().unwrap() = timeout;
+    if timeout.is_none() {
+        revoke_all()?;
+    }
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    TIMEOUT.lock().unwrap().is_some()
+}
+
+/// Store the key used to decrypt the biometric secret of `service` and `account`, if enabled.
+pub fn store(service: &str, account: &str, key: &[u8]) -> Result<()> {
+    match *TIMEOUT.lock().unwrap() {
+        Some(timeout) => Keyring::find_or_create(KEYRING_NAME)?.store(
+            &description(service, account),
+            key,
+            timeout,
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Load a cached key that has not yet expired.
+pub fn load(service: &str, account: &str) -> Result<Option<Vec<u8>>> {
+    if !is_enabled() {
+        return Ok(None);
+    }
+    match Keyring::find(KEYRING_NAME)? {
+        Some(keyring) => keyring.load(&description(service, account)),
+        None => Ok(None),
+    }
+}
+
+/// Revoke the cached key of `service` and `account`.
+pub fn revoke(service: &str, account: &str) -> Result<()> {
+    match Keyring::find(KEYRING_NAME)? {
+        Some(keyring) => keyring.revoke(&description(service, account)),
+        None => Ok(()),
+    }
+}
+
+/// Revoke all cached keys, e.g. when the vault is locked.
+pub fn revoke_all() -> Result<()> {
+    match Keyring::find(KEYRING_NAME)? {
+        Some(keyring) => keyring.revoke_all(),
+        None => Ok(()),
+    }
+}
+
+fn description(service: &str, account: &str) -> String {
+    format!("{}/{}", service, account)
+}
+
+/// A keyring linked into the session keyring.
+struct Keyring {
+    serial: i32,
+}
+
+impl Keyring {
+    fn find(name: &str) -> Result<Option<Self>> {
+        Ok(search(KEY_SPEC_SESSION_KEYRING, "keyring", name)?.map(|serial| Keyring { serial }))
+    }
+
+    fn find_or_create(name: &str) -> Result<Self> {
+        if let Some(keyring) = Self::find(name)? {
+            return Ok(keyring);
+        }
+
+        let serial = add_key("keyring", name, &[], KEY_SPEC_SESSION_KEYRING)?;
+        keyctl(KEYCTL_SETPERM, serial as c_long, POSSESSOR_PERMISSIONS as c_long, 0, 0)?;
+        Ok(Keyring { serial })
+    }
+
+    fn store(&self, description: &str, payload: &[u8], timeout: Duration) -> Result<()> {
+        // Updates the payload if the key already exists
+        let serial = add_key("user", description, payload, self.serial)?;
+        keyctl(KEYCTL_SETPERM, serial as c_long, POSSESSOR_PERMISSIONS as c_long, 0, 0)?;
+        keyctl(
+            KEYCTL_SET_TIMEOUT,
+            serial as c_long,
+            timeout.as_secs().max(1) as c_long,
+            0,
+            0,
+        )?;
+        Ok(())
+    }
+
+    fn load(&self, description: &str) -> Result<Option<Vec<u8>>> {
+        match search(self.serial, "user", description)? {
+            Some(serial) => Ok(Some(read(serial)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn revoke(&self, description: &str) -> Result<()> {
+        if let Some(serial) = search(self.serial, "user", description)? {
+            keyctl(KEYCTL_REVOKE, serial as c_long, 0, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    fn revoke_all(&self) -> Result<()> {
+        // Reading a keyring returns the serials of the keys it contains
+        let serials: Vec<i32> = read(self.serial)?
+            .chunks_exact(4)
+            .map(|serial| i32::from_ne_bytes(serial.try_into().unwrap()))
+            .collect();
+
+        // Revoke rather than only unlink, so the keys are unusable even if linked elsewhere. A
+        // failure must not leave the remaining keys usable, so it is only reported at the end.
+        let mut result = Ok(());
+        for serial in serials {
+            match sys_keyctl(KEYCTL_REVOKE, serial as c_long, 0, 0, 0) {
+                // Expired keys are listed until garbage collected, but can no longer be used
+                Err(e) if matches!(
+                    e.raw_os_error(),
+                    Some(libc::EKEYEXPIRED) | Some(libc::EKEYREVOKED)
+                ) => {}
+                Err(e) if result.is_ok() => {
+                    result = Err(anyhow!("failed to revoke cached key {}", e));
+                }
+                _ => {}
+            }
+        }
+        keyctl(KEYCTL_CLEAR, self.serial as c_long, 0, 0, 0)?;
+        result
+    }
+}
+
+fn add_key(key_type: &str, description: &str, payload: &[u8], keyring: i32) -> Result<i32> {
+    let key_type = CString::new(key_type)?;
+    let description = CString::new(description)?;
+    let payload_ptr = if payload.is_empty() {
+        std::ptr::null()
+    } else {
+        payload.as_ptr()
+    };
+
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            payload_ptr,
+            payload.len(),
+            keyring,
+        )
+    };
+    if serial < 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(anyhow!("failed to add key to the session keyring {}", e));
+    }
+    Ok(serial as i32)
+}
+
+fn search(keyring: i32, key_type: &str, description: &str) -> Result<Option<i32>> {
+    let key_type = CString::new(key_type)?;
+    let description = CString::new(description)?;
+
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_SEARCH,
+            keyring,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            0,
+        )
+    };
+    if serial < 0 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            // Expired and revoked keys are reported as not found
+            Some(libc::ENOKEY) | Some(libc::EKEYEXPIRED) | Some(libc::EKEYREVOKED) => Ok(None),
+            _ => Err(anyhow!("failed to search the session keyring {}", e)),
+        };
+    }
+    Ok(Some(serial as i32))
+}
+
+fn read(serial: i32) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    loop {
+        // Returns the size of the payload, which may exceed the buffer
+        let len = keyctl(
+            KEYCTL_READ,
+            serial as c_long,
+            buffer.as_mut_ptr() as c_long,
+            buffer.len() as c_long,
+            0,
+        )? as usize;
+
+        if len <= buffer.len() {
+            buffer.truncate(len);
+            return Ok(buffer);
+        }
+        buffer.resize(len, 0);
+    }
+}
+
+fn keyctl(operation: u32, arg2: c_long, arg3: c_long, arg4: c_long, arg5: c_long) -> Result<c_long> {
+    sys_keyctl(operation, arg2, arg3, arg4, arg5)
+        .map_err(|e| anyhow!("keyctl operation {} failed {}", operation, e))
+}
+
+fn sys_keyctl(
+    operation: u32,
+    arg2: c_long,
+    arg3: c_long,
+    arg4: c_long,
+    arg5: c_long,
+) -> std::io::Result<c_long> {
+    let result = unsafe { libc::syscall(libc::SYS_keyctl, operation, arg2, arg3, arg4, arg5) };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unlinks the keyring from the session keyring when dropped
+    struct TestKeyring(Keyring);
+
+    impl std::ops::Deref for TestKeyring {
+        type Target = Keyring;
+
+        fn deref(&self) -> &Keyring {
+            &self.0
+        }
+    }
+
+    impl Drop for TestKeyring {
+        fn drop(&mut self) {
+            let _ = keyctl(
+                libc::KEYCTL_UNLINK,
+                self.0.serial as c_long,
+                KEY_SPEC_SESSION_KEYRING as c_long,
+                0,
+                0,
+            );
+        }
+    }
+
+    // `None` if keyctl is unavailable, e.g. blocked by the default seccomp profile of containers
+    fn keyring(name: &str) -> Option<TestKeyring> {
+        let probe = sys_keyctl(
+            libc::KEYCTL_GET_KEYRING_ID,
+            KEY_SPEC_SESSION_KEYRING as c_long,
+            0,
+            0,
+            0,
+        );
+        if let Err(e) = probe {
+            if matches!(e.raw_os_error(), Some(libc::EPERM) | Some(libc::ENOSYS)) {
+                eprintln!("keyctl is unavailable, skipping: {}", e);
+                return None;
+            }
+        }
+        Some(TestKeyring(
+            Keyring::find_or_create(&format!("bitwarden_test_{}", name)).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn store_and_load() {
+        let Some(keyring) = keyring("store_and_load") else {
+            return;
+        };
+        keyring
+            .store("service/account", b"key", Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(
+            keyring.load("service/account").unwrap(),
+            Some(b"key".to_vec())
+        );
+        assert_eq!(keyring.load("service/other").unwrap(), None);
+    }
+
+    #[test]
+    fn store_replaces_existing_key() {
+        let Some(keyring) = keyring("store_replaces_existing_key") else {
+            return;
+        };
+        keyring
+            .store("service/account", b"old", Duration::from_secs(60))
+            .unwrap();
+        keyring
+            .store("service/account", b"new", Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(
+            keyring.load("service/account").unwrap(),
+            Some(b"new".to_vec())
+        );
+    }
+
+    #[test]
+    fn load_expired_key() {
+        let Some(keyring) = keyring("load_expired_key") else {
+            return;
+        };
+        keyring
+            .store("service/account", b"key", Duration::from_secs(1))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(1500));
+
+        assert_eq!(keyring.load("service/account").unwrap(), None);
+    }
+
+    #[test]
+    fn revoke_all_keys() {
+        let Some(keyring) = keyring("revoke_all_keys") else {
+            return;
+        };
+        keyring
+            .store("service/account", b"key", Duration::from_secs(60))
+            .unwrap();
+        keyring
+            .store("service/other", b"key", Duration::from_secs(60))
+            .unwrap();
+
+        keyring.revoke_all().unwrap();
+        assert_eq!(keyring.load("service/account").unwrap(), None);
+        assert_eq!(keyring.load("service/other").unwrap(), None);
+    }
+
+    #[test]
+    fn revoke_single_key() {
+        let Some(keyring) = keyring("revoke_single_key") else {
+            return;
+        };
+        keyring
+            .store("service/account", b"key", Duration::from_secs(60))
+            .unwrap();
+        keyring
+            .store("service/other", b"key", Duration::from_secs(60))
+            .unwrap();
+
+        keyring.revoke("service/account").unwrap();
+        assert_eq!(keyring.load("service/account").unwrap(), None);
+        assert_eq!(
+            keyring.load("service/other").unwrap(),
+            Some(b"key".to_vec())
+        );
+    }
+
+    #[test]
+    fn revoke_all_skips_expired_keys() {
+        let Some(keyring) = keyring("revoke_all_skips_expired_keys") else {
+            return;
+        };
+        keyring
+            .store("service/expired", b"key", Duration::from_secs(1))
+            .unwrap();
+        keyring
+            .store("service/account", b"key", Duration::from_secs(60))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(1500));
+
+        keyring.revoke_all().unwrap();
+        assert_eq!(keyring.load("service/account").unwrap(), None);
+        assert!(read(keyring.serial).unwrap().is_empty());
+    }
+
+    #[test]
+    fn permissions_are_restricted_to_possessor() {
+        let Some(keyring) = keyring("permissions_are_restricted_to_possessor") else {
+            return;
+        };
+        keyring
+            .store("service/account", b"key", Duration::from_secs(60))
+            .unwrap();
+        let serial = search(keyring.serial, "user", "service/account")
+            .unwrap()
+            .unwrap();
+
+        // KEYCTL_DESCRIBE returns "type;uid;gid;perm;description"
+        let mut buffer = vec![0u8; 256];
+        let len = keyctl(
+            libc::KEYCTL_DESCRIBE,
+            serial as c_long,
+            buffer.as_mut_ptr() as c_long,
+            buffer.len() as c_long,
+            0,
+        )
+        .unwrap() as usize;
+        let description = String::from_utf8_lossy(&buffer[..len - 1]).into_owned();
+        let perm = description.split(';').nth(3).unwrap();
+        assert_eq!(
+            u32::from_str_radix(perm, 16).unwrap(),
+            POSSESSOR_PERMISSIONS
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::{bail, Result};
 
-use crate::biometric::{KeyMaterial, OsDerivedKey};
+use crate::{
+    biometric::{KeyMaterial, OsDerivedKey},
+    secret::SecretBuf,
+};
 
 /// The MacOS implementation of the biometric trait.
 pub struct Biometric {}
@@ -10,6 +13,10 @@ impl super::BiometricTrait for Biometric {
         bail!("platform not supported");
     }
 
+    async fn cancel_prompt() -> Result<()> {
+        bail!("platform not supported");
+    }
+
     async fn available() -> Result<bool> {
         bail!("platform not supported");
     }
@@ -22,7 +29,7 @@ impl super::BiometricTrait for Biometric {
         _service: &str,
         _account: &str,
         _key_material: Option<KeyMaterial>,
-    ) -> Result<String> {
+    ) -> Result<SecretBuf> {
         bail!("platform not supported");
     }
 
@@ -35,4 +42,20 @@ impl super::BiometricTrait for Biometric {
     ) -> Result<String> {
         bail!("platform not supported");
     }
+
+    fn has_biometric_secret(_service: &str, _account: &str) -> Result<bool> {
+        bail!("platform not supported");
+    }
+
+    fn delete_biometric_secret(_service: &str, _account: &str) -> Result<()> {
+        bail!("platform not supported");
+    }
+
+    fn rotate_biometric_secret(
+        _service: &str,
+        _account: &str,
+        _key_material: Option<KeyMaterial>,
+    ) -> Result<OsDerivedKey> {
+        bail!("platform not supported");
+    }
 }
@@ -6,11 +6,28 @@ use anyhow::{anyhow, Result};
 #[cfg_attr(target_os = "macos", path = "macos.rs")]
 mod biometric;
 
+mod envelope;
+#[cfg(target_os = "linux")]
+pub mod fprintd;
+#[cfg(target_os = "linux")]
+pub mod key_cache;
+#[cfg(target_os = "linux")]
+pub mod pkcs11;
+#[cfg(target_os = "linux")]
+pub mod polkit;
+#[cfg(target_os = "linux")]
+pub mod tpm;
+
 pub use biometric::Biometric;
+use std::sync::Mutex;
+
+use crate::secret::SecretBuf;
+
 use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
 use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::Sender;
 
-use crate::crypto::{self, CipherString};
+use envelope::{EnvelopeKey, StoredSecret};
 
 pub struct KeyMaterial {
     pub os_key_part_b64: String,
@@ -22,9 +39,65 @@ pub struct OsDerivedKey {
     pub iv_b64: String,
 }
 
+/// Progress of a pending biometric prompt, e.g. asking the user to swipe their finger again.
+///
+/// Currently only reported by fingerprint readers on Linux.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyStatus {
+    /// The prompt started and waits for the user, e.g. to place their finger on the reader.
+    Waiting,
+    Match,
+    NoMatch,
+    RetryScan,
+    SwipeTooShort,
+    FingerNotCentered,
+    RemoveAndRetry,
+    Disconnected,
+    UnknownError,
+}
+
+impl VerifyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerifyStatus::Waiting => "waiting",
+            VerifyStatus::Match => "match",
+            VerifyStatus::NoMatch => "no-match",
+            VerifyStatus::RetryScan => "retry-scan",
+            VerifyStatus::SwipeTooShort => "swipe-too-short",
+            VerifyStatus::FingerNotCentered => "finger-not-centered",
+            VerifyStatus::RemoveAndRetry => "remove-and-retry",
+            VerifyStatus::Disconnected => "disconnected",
+            VerifyStatus::UnknownError => "unknown-error",
+        }
+    }
+}
+
+/// A [VerifyStatus] together with the message passed to the prompt, for backends without a dialog
+/// of their own to show it in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyProgress {
+    pub status: VerifyStatus,
+    pub message: String,
+}
+
+static VERIFY_STATUS_LISTENER: Mutex<Option<Sender<VerifyProgress>>> = Mutex::new(None);
+
+/// Register a listener for the progress of biometric prompts, replacing any previous listener.
+pub fn on_verify_status(tx: Sender<VerifyProgress>) {
+    *VERIFY_STATUS_LISTENER.lock().unwrap() = Some(tx);
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn verify_status_listener() -> Option<Sender<VerifyProgress>> {
+    VERIFY_STATUS_LISTENER.lock().unwrap().clone()
+}
+
 pub trait BiometricTrait {
     #[allow(async_fn_in_trait)]
     async fn prompt(hwnd: Vec<u8>, message: String) -> Result<bool>;
+    /// Cancel a prompt started with [BiometricTrait::prompt]. The pending prompt resolves to `false`.
+    #[allow(async_fn_in_trait)]
+    async fn cancel_prompt() -> Result<()>;
     #[allow(async_fn_in_trait)]
     async fn available() -> Result<bool>;
     fn derive_key_material(secret: Option<&str>) -> Result<OsDerivedKey>;
@@ -39,29 +112,78 @@ pub trait BiometricTrait {
         service: &str,
         account: &str,
         key_material: Option<KeyMaterial>,
-    ) -> Result<String>;
+    ) -> Result<SecretBuf>;
+    fn has_biometric_secret(service: &str, account: &str) -> Result<bool>;
+    fn delete_biometric_secret(service: &str, account: &str) -> Result<()>;
+    /// Re-encrypt the stored secret with a key derived from a new OS key part. Returns the newly
+    /// derived key, whose iv must be used for future calls.
+    fn rotate_biometric_secret(
+        service: &str,
+        account: &str,
+        key_material: Option<KeyMaterial>,
+    ) -> Result<OsDerivedKey>;
 }
 
 
-fn encrypt(secret: &str, key_material: &KeyMaterial, iv_b64: &str) -> Result<String> {
+fn encrypt(secret: &str, key: &EnvelopeKey, iv_b64: &str) -> Result<String> {
     let iv = base64_engine
         .decode(iv_b64)?
         .try_into()
         .map_err(|e: Vec<_>| anyhow!("Expected length {}, got {}", 16, e.len()))?;
 
-    let encrypted = crypto::encrypt_aes256(secret.as_bytes(), iv, key_material.derive_key()?)?;
-
-    Ok(encrypted.to_string())
+    envelope::seal(secret, key, iv)
 }
 
-fn decrypt(secret: &CipherString, key_material: &KeyMaterial) -> Result<String> {
-    if let CipherString::AesCbc256_B64 { iv, data } = secret {
-        let decrypted = crypto::decrypt_aes256(&iv, &data, key_material.derive_key()?)?;
+/// Decrypts the secret stored for `service` and `account`. Entries in an older format are
+/// re-encrypted with the current envelope, see [envelope].
+///
+/// `key_material` is only needed to upgrade entries encrypted with the legacy key derivation.
+fn read_secret(
+    service: &str,
+    account: &str,
+    key: &EnvelopeKey,
+    key_material: Option<&KeyMaterial>,
+) -> Result<SecretBuf> {
+    let stored: StoredSecret = crate::password::get_password(service, account)?
+        .as_str()?
+        .parse()?;
+    let secret = stored.decrypt(key, key_material)?;
 
-        Ok(String::from_utf8(decrypted)?)
-    } else {
-        Err(anyhow!("Invalid cipher string"))
+    if stored.needs_upgrade() {
+        let upgraded = envelope::seal(secret.as_str()?, key, rand::random())?;
+        // A failed write leaves the old entry in place, so the upgrade is retried on the next read
+        crate::password::set_password(service, account, &upgraded)?;
     }
+    Ok(secret)
+}
+
+/// Decrypts the stored secret with `key_material` and re-encrypts it with freshly derived key
+/// material. The stored entry is only replaced once the new secret has been encrypted, so a failure
+/// leaves the old secret intact.
+fn rotate<T: BiometricTrait>(
+    service: &str,
+    account: &str,
+    key_material: KeyMaterial,
+) -> Result<OsDerivedKey> {
+    // Rewritten below in the current format regardless, so no upgrade is needed
+    let stored: StoredSecret = crate::password::get_password(service, account)?
+        .as_str()?
+        .parse()?;
+    let secret = stored.decrypt(&EnvelopeKey::derive(&key_material)?, Some(&key_material))?;
+
+    let derived = T::derive_key_material(None)?;
+    let new_key_material = KeyMaterial {
+        os_key_part_b64: derived.key_b64.clone(),
+        client_key_part_b64: key_material.client_key_part_b64,
+    };
+    let encrypted_secret = encrypt(
+        secret.as_str()?,
+        &EnvelopeKey::derive(&new_key_material)?,
+        &derived.iv_b64,
+    )?;
+
+    crate::password::set_password(service, account, &encrypted_secret)?;
+    Ok(derived)
 }
 
 impl KeyMaterial {
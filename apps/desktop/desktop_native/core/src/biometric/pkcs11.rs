@@ -0,0 +1,1151 @@
+//! Protect the biometric OS key part with a key on a PKCS#11 token, e.g. a smart card.
+//!
+//! RSA keys wrap the key part using RSA-OAEP. EC keys (P-256 only) wrap it with a key agreed
+//! between an ephemeral key and the token key using ECDH. Unwrapping requires logging in to the
+//! token, the PIN is provided by the caller through [login].
+
+use std::{
+    collections::HashMap,
+    ffi::{c_void, OsStr},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use aes::cipher::generic_array::GenericArray;
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use hkdf::Hkdf;
+use libc::c_ulong;
+use p256::{ecdh::EphemeralSecret, elliptic_curve::sec1::ToEncodedPoint, PublicKey};
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::{
+    crypto::{self, CipherString},
+    secret::SecretBuf,
+};
+
+// The wrapped OS key part is stored next to the biometric secrets, see the TPM sealed key
+const WRAPPED_KEY_SERVICE: &str = "Bitwarden_biometric";
+const WRAPPED_KEY_ACCOUNT: &str = "pkcs11_wrapped_os_key_part";
+// The enabled token and key, so the setting survives a restart
+const CONFIG_ACCOUNT: &str = "pkcs11_config";
+
+const ECDH_INFO: &[u8] = b"bitwarden-desktop-pkcs11-ecdh";
+
+// DER encoded OID of the P-256 curve, as found in CKA_EC_PARAMS
+const P256_OID: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+// Modules loaded by this process. C_Initialize and C_Finalize are process wide, so each module is
+// only loaded once and finalized when the last state, session or listing using it is dropped.
+static MODULES: Mutex<Option<HashMap<PathBuf, Weak<Module>>>> = Mutex::new(None);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pkcs11Config {
+    /// Path of the PKCS#11 module, e.g. `/usr/lib/x86_64-linux-gnu/opensc-pkcs11.so`.
+    pub module: PathBuf,
+    /// Serial number of the token holding the key.
+    pub token_serial: String,
+    /// `CKA_ID` of the key pair.
+    pub key_id: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub label: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub serial: String,
+    /// The token has a PIN pad, so [login] must be called without a PIN.
+    pub protected_authentication_path: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    Rsa,
+    Ec,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub id: Vec<u8>,
+    pub label: String,
+    pub key_type: KeyType,
+}
+
+struct State {
+    config: Pkcs11Config,
+    module: Arc<Module>,
+    slot: c_ulong,
+    // Logged in session, consumed by the next unwrap
+    session: Option<Session>,
+}
+
+impl State {
+    fn new(config: Pkcs11Config) -> Result<Self> {
+        let module = Module::load(&config.module)?;
+        let slot = module.find_slot(&config.token_serial)?;
+        Ok(State {
+            config,
+            module,
+            slot,
+            session: None,
+        })
+    }
+
+    /// The state of the enabled token, restored from the stored config after a restart.
+    fn get(state: &mut Option<State>) -> Result<&mut State> {
+        if state.is_none() {
+            let config = stored_config()?.ok_or(anyhow!("PKCS#11 unlock is not enabled"))?;
+            *state = Some(State::new(config)?);
+        }
+        Ok(state.as_mut().unwrap())
+    }
+}
+
+/// Wrap the biometric OS key part with the configured token key. Applies to keys derived after
+/// this call.
+pub fn enable(config: Pkcs11Config) -> Result<()> {
+    let state = State::new(config)?;
+
+    // Fail early if the key does not exist, rather than on the next unlock
+    let session = Session::open(&state.module, state.slot)?;
+    session.find_key(&state.config.key_id, CKO_PUBLIC_KEY)?;
+    drop(session);
+
+    crate::password::set_password(
+        WRAPPED_KEY_SERVICE,
+        CONFIG_ACCOUNT,
+        &state.config.to_string(),
+    )?;
+    *STATE.lock().unwrap() = Some(state);
+    Ok(())
+}
+
+pub fn disable() -> Result<()> {
+    *STATE.lock().unwrap() = None;
+    crate::password::delete_password(WRAPPED_KEY_SERVICE, CONFIG_ACCOUNT)
+}
+
+/// The enabled token and key, if any.
+pub fn config() -> Result<Option<Pkcs11Config>> {
+    if let Some(state) = STATE.lock().unwrap().as_ref() {
+        return Ok(Some(state.config.clone()));
+    }
+    stored_config()
+}
+
+fn stored_config() -> Result<Option<Pkcs11Config>> {
+    match crate::password::find_password(WRAPPED_KEY_SERVICE, CONFIG_ACCOUNT)? {
+        Some(config) => Ok(Some(config.as_str()?.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// List the tokens present in the slots of `module`.
+pub fn list_tokens(module: &Path) -> Result<Vec<TokenInfo>> {
+    let module = Module::load(module)?;
+    module
+        .slots()?
+        .into_iter()
+        .map(|slot| module.token_info(slot))
+        .collect()
+}
+
+/// List the RSA and EC key pairs on the token with `token_serial`. Only public keys are listed, so
+/// no login is required.
+pub fn list_keys(module: &Path, token_serial: &str) -> Result<Vec<KeyInfo>> {
+    let module = Module::load(module)?;
+    let slot = module.find_slot(token_serial)?;
+    let session = Session::open(&module, slot)?;
+
+    let mut keys = Vec::new();
+    for object in session.find_objects(&[Attribute::ulong(CKA_CLASS, CKO_PUBLIC_KEY)])? {
+        let key_type = match session.get_ulong(object, CKA_KEY_TYPE)? {
+            CKK_RSA => KeyType::Rsa,
+            CKK_EC => KeyType::Ec,
+            _ => continue,
+        };
+        keys.push(KeyInfo {
+            id: session.get_bytes(object, CKA_ID)?,
+            label: String::from_utf8_lossy(&session.get_bytes(object, CKA_LABEL)?).into_owned(),
+            key_type,
+        });
+    }
+    Ok(keys)
+}
+
+/// Log in to the configured token. `None` logs in through the PIN pad of the reader.
+///
+/// The login is consumed by the next unlock, so the PIN is required for every unlock.
+pub fn login(pin: Option<&str>) -> Result<()> {
+    let mut state = STATE.lock().unwrap();
+    let state = State::get(&mut state)?;
+
+    let mut session = Session::open(&state.module, state.slot)?;
+    session.login(pin)?;
+    state.session = Some(session);
+    Ok(())
+}
+
+/// Returns the random OS key part wrapped by the token key, creating and storing it on first use.
+///
+/// Unwrapping an existing key part requires a previous [login].
+pub fn os_key_part(config: &Pkcs11Config) -> Result<SecretBuf> {
+    let mut state = STATE.lock().unwrap();
+    let state = State::get(&mut state)?;
+    if state.config != *config {
+        bail!("PKCS#11 unlock is not enabled");
+    }
+    let session = state.session.take();
+
+    // Only a missing key part is created, replacing it on other errors would lose the secrets
+    // derived from it
+    match crate::password::find_password(WRAPPED_KEY_SERVICE, WRAPPED_KEY_ACCOUNT)? {
+        Some(wrapped) => {
+            let session =
+                session.ok_or(anyhow!("Token login is required to unwrap the PKCS#11 key"))?;
+            unwrap(&session, &config.key_id, &wrapped.as_str()?.parse()?)
+        }
+        None => {
+            let mut key_part = SecretBuf::new(32)?;
+            rand::thread_rng().fill_bytes(key_part.as_mut_bytes());
+
+            let session = match session {
+                Some(session) => session,
+                None => Session::open(&state.module, state.slot)?,
+            };
+            let wrapped = wrap(&session, &config.key_id, &key_part)?;
+            crate::password::set_password(
+                WRAPPED_KEY_SERVICE,
+                WRAPPED_KEY_ACCOUNT,
+                &wrapped.to_string(),
+            )?;
+            Ok(key_part)
+        }
+    }
+}
+
+/// Remove the wrapped OS key part, e.g. when token unlock is disabled.
+pub fn delete_os_key_part() -> Result<()> {
+    crate::password::delete_password(WRAPPED_KEY_SERVICE, WRAPPED_KEY_ACCOUNT)
+}
+
+// Stored as `<module>|<token serial>|<key id>`, each base64 encoded as paths and serials may
+// contain the separator
+impl std::fmt::Display for Pkcs11Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}|{}|{}",
+            base64_engine.encode(self.module.as_os_str().as_bytes()),
+            base64_engine.encode(&self.token_serial),
+            base64_engine.encode(&self.key_id)
+        )
+    }
+}
+
+impl std::str::FromStr for Pkcs11Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split('|').collect();
+        let [module, token_serial, key_id] = parts[..] else {
+            bail!(
+                "Invalid PKCS#11 config, expected 3 parts, got {}",
+                parts.len()
+            );
+        };
+
+        Ok(Pkcs11Config {
+            module: OsStr::from_bytes(&base64_engine.decode(module)?).into(),
+            token_serial: String::from_utf8(base64_engine.decode(token_serial)?)?,
+            key_id: base64_engine.decode(key_id)?,
+        })
+    }
+}
+
+/// An OS key part wrapped by a token key.
+#[derive(Debug, PartialEq, Eq)]
+enum WrappedKey {
+    RsaOaep(Vec<u8>),
+    /// The public ephemeral key as an uncompressed point, and the key part encrypted with the
+    /// agreed key.
+    EcdhP256 {
+        ephemeral: Vec<u8>,
+        secret: String,
+    },
+}
+
+impl std::fmt::Display for WrappedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WrappedKey::RsaOaep(data) => write!(f, "rsa-oaep:{}", base64_engine.encode(data)),
+            WrappedKey::EcdhP256 { ephemeral, secret } => write!(
+                f,
+                "ecdh-p256:{}:{}",
+                base64_engine.encode(ephemeral),
+                secret
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for WrappedKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split(':').collect();
+        match parts[..] {
+            ["rsa-oaep", data] => Ok(WrappedKey::RsaOaep(base64_engine.decode(data)?)),
+            ["ecdh-p256", ephemeral, secret] => Ok(WrappedKey::EcdhP256 {
+                ephemeral: base64_engine.decode(ephemeral)?,
+                secret: secret.to_owned(),
+            }),
+            _ => bail!("Invalid PKCS#11 wrapped key"),
+        }
+    }
+}
+
+fn wrap(session: &Session, key_id: &[u8], secret: &[u8]) -> Result<WrappedKey> {
+    let key = session.find_key(key_id, CKO_PUBLIC_KEY)?;
+    match session.get_ulong(key, CKA_KEY_TYPE)? {
+        CKK_RSA => Ok(WrappedKey::RsaOaep(session.encrypt_rsa_oaep(key, secret)?)),
+        CKK_EC => {
+            check_p256(&session.get_bytes(key, CKA_EC_PARAMS)?)?;
+            let point = session.get_bytes(key, CKA_EC_POINT)?;
+            let token_key = PublicKey::from_sec1_bytes(decode_ec_point(&point))
+                .map_err(|_| anyhow!("Invalid PKCS#11 EC public key"))?;
+            wrap_ecdh(&token_key, secret)
+        }
+        key_type => bail!("Unsupported PKCS#11 key type {}", key_type),
+    }
+}
+
+fn unwrap(session: &Session, key_id: &[u8], wrapped: &WrappedKey) -> Result<SecretBuf> {
+    let key = session.find_key(key_id, CKO_PRIVATE_KEY)?;
+    match wrapped {
+        WrappedKey::RsaOaep(data) => Ok(SecretBuf::from_vec(session.decrypt_rsa_oaep(key, data)?)?),
+        WrappedKey::EcdhP256 { ephemeral, secret } => {
+            let shared = SecretBuf::from_vec(session.derive_ecdh(key, ephemeral)?)?;
+            unwrap_ecdh(&shared, ephemeral, secret)
+        }
+    }
+}
+
+fn wrap_ecdh(token_key: &PublicKey, secret: &[u8]) -> Result<WrappedKey> {
+    let ephemeral = EphemeralSecret::random(&mut rand::rngs::OsRng);
+    let ephemeral_point = ephemeral
+        .public_key()
+        .to_encoded_point(false)
+        .as_bytes()
+        .to_vec();
+    let shared = ephemeral.diffie_hellman(token_key);
+
+    let (enc, mac) = ecdh_keys(shared.raw_secret_bytes(), &ephemeral_point)?;
+    let encrypted = crypto::encrypt_aes256_hmac(secret, rand::random(), mac, enc)?;
+    Ok(WrappedKey::EcdhP256 {
+        ephemeral: ephemeral_point,
+        secret: encrypted.to_string(),
+    })
+}
+
+fn unwrap_ecdh(shared: &[u8], ephemeral_point: &[u8], secret: &str) -> Result<SecretBuf> {
+    let (enc, mac) = ecdh_keys(shared, ephemeral_point)?;
+    match secret.parse()? {
+        CipherString::AesCbc256_HmacSha256_B64 { iv, mac: tag, data } => {
+            Ok(crypto::decrypt_aes256_hmac(&iv, &tag, &data, mac, enc)?)
+        }
+        _ => bail!("Invalid PKCS#11 wrapped key"),
+    }
+}
+
+type Key = GenericArray<u8, typenum::U32>;
+
+// The raw ECDH output is not uniformly random, so it is passed through HKDF, salted with the
+// ephemeral key to bind the derived keys to this exchange
+fn ecdh_keys(shared: &[u8], ephemeral_point: &[u8]) -> Result<(Key, Key)> {
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(ephemeral_point), shared)
+        .expand(ECDH_INFO, &mut okm)
+        .map_err(|e| anyhow!("Failed to derive wrapping key: {}", e))?;
+    Ok((
+        GenericArray::clone_from_slice(&okm[..32]),
+        GenericArray::clone_from_slice(&okm[32..]),
+    ))
+}
+
+fn check_p256(ec_params: &[u8]) -> Result<()> {
+    if ec_params != P256_OID {
+        bail!("Only P-256 keys are supported");
+    }
+    Ok(())
+}
+
+// CKA_EC_POINT is a DER encoded OCTET STRING, though some modules return the raw point
+fn decode_ec_point(value: &[u8]) -> &[u8] {
+    match value {
+        [0x04, len, point @ ..] if *len as usize == point.len() && point.first() == Some(&0x04) => {
+            point
+        }
+        point => point,
+    }
+}
+
+// Subset of the PKCS#11 2.40 API, see
+// https://docs.oasis-open.org/pkcs11/pkcs11-base/v2.40/os/pkcs11-base-v2.40-os.html
+const CKR_OK: c_ulong = 0x0;
+const CKR_PIN_INCORRECT: c_ulong = 0xa0;
+const CKR_PIN_LOCKED: c_ulong = 0xa4;
+const CKR_USER_ALREADY_LOGGED_IN: c_ulong = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: c_ulong = 0x191;
+
+const CKF_OS_LOCKING_OK: c_ulong = 0x2;
+const CKF_SERIAL_SESSION: c_ulong = 0x4;
+const CKF_PROTECTED_AUTHENTICATION_PATH: c_ulong = 0x100;
+
+const CKU_USER: c_ulong = 1;
+
+const CKA_CLASS: c_ulong = 0x0;
+const CKA_TOKEN: c_ulong = 0x1;
+const CKA_LABEL: c_ulong = 0x3;
+const CKA_VALUE: c_ulong = 0x11;
+const CKA_KEY_TYPE: c_ulong = 0x100;
+const CKA_ID: c_ulong = 0x102;
+const CKA_SENSITIVE: c_ulong = 0x103;
+const CKA_VALUE_LEN: c_ulong = 0x161;
+const CKA_EXTRACTABLE: c_ulong = 0x162;
+const CKA_EC_PARAMS: c_ulong = 0x180;
+const CKA_EC_POINT: c_ulong = 0x181;
+
+const CKO_PUBLIC_KEY: c_ulong = 2;
+const CKO_PRIVATE_KEY: c_ulong = 3;
+const CKO_SECRET_KEY: c_ulong = 4;
+
+const CKK_RSA: c_ulong = 0x0;
+const CKK_EC: c_ulong = 0x3;
+const CKK_GENERIC_SECRET: c_ulong = 0x10;
+
+const CKM_RSA_PKCS_OAEP: c_ulong = 0x9;
+const CKM_SHA256: c_ulong = 0x250;
+const CKM_ECDH1_DERIVE: c_ulong = 0x1050;
+const CKG_MGF1_SHA256: c_ulong = 0x2;
+const CKZ_DATA_SPECIFIED: c_ulong = 0x1;
+const CKD_NULL: c_ulong = 0x1;
+
+#[repr(C)]
+struct CkVersion {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct CkTokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: c_ulong,
+    max_session_count: c_ulong,
+    session_count: c_ulong,
+    max_rw_session_count: c_ulong,
+    rw_session_count: c_ulong,
+    max_pin_len: c_ulong,
+    min_pin_len: c_ulong,
+    total_public_memory: c_ulong,
+    free_public_memory: c_ulong,
+    total_private_memory: c_ulong,
+    free_private_memory: c_ulong,
+    hardware_version: CkVersion,
+    firmware_version: CkVersion,
+    utc_time: [u8; 16],
+}
+
+#[repr(C)]
+struct CkAttribute {
+    attribute_type: c_ulong,
+    value: *mut c_void,
+    value_len: c_ulong,
+}
+
+#[repr(C)]
+struct CkMechanism {
+    mechanism: c_ulong,
+    parameter: *mut c_void,
+    parameter_len: c_ulong,
+}
+
+#[repr(C)]
+struct CkRsaPkcsOaepParams {
+    hash_alg: c_ulong,
+    mgf: c_ulong,
+    source: c_ulong,
+    source_data: *mut c_void,
+    source_data_len: c_ulong,
+}
+
+#[repr(C)]
+struct CkEcdh1DeriveParams {
+    kdf: c_ulong,
+    shared_data_len: c_ulong,
+    shared_data: *mut u8,
+    public_data_len: c_ulong,
+    public_data: *mut u8,
+}
+
+#[repr(C)]
+struct CkInitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: c_ulong,
+    reserved: *mut c_void,
+}
+
+type Rv = c_ulong;
+
+// Only the functions used are typed, the others are padding to keep the layout of
+// CK_FUNCTION_LIST
+#[repr(C)]
+struct CkFunctionList {
+    version: CkVersion,
+    initialize: unsafe extern "C" fn(*mut c_void) -> Rv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> Rv,
+    _get_info: [usize; 2],
+    get_slot_list: unsafe extern "C" fn(u8, *mut c_ulong, *mut c_ulong) -> Rv,
+    _get_slot_info: usize,
+    get_token_info: unsafe extern "C" fn(c_ulong, *mut CkTokenInfo) -> Rv,
+    _get_mechanism_list: [usize; 5],
+    open_session:
+        unsafe extern "C" fn(c_ulong, c_ulong, *mut c_void, *mut c_void, *mut c_ulong) -> Rv,
+    close_session: unsafe extern "C" fn(c_ulong) -> Rv,
+    _close_all_sessions: [usize; 4],
+    login: unsafe extern "C" fn(c_ulong, c_ulong, *const u8, c_ulong) -> Rv,
+    logout: unsafe extern "C" fn(c_ulong) -> Rv,
+    _create_object: [usize; 2],
+    destroy_object: unsafe extern "C" fn(c_ulong, c_ulong) -> Rv,
+    _get_object_size: usize,
+    get_attribute_value: unsafe extern "C" fn(c_ulong, c_ulong, *mut CkAttribute, c_ulong) -> Rv,
+    _set_attribute_value: usize,
+    find_objects_init: unsafe extern "C" fn(c_ulong, *mut CkAttribute, c_ulong) -> Rv,
+    find_objects: unsafe extern "C" fn(c_ulong, *mut c_ulong, c_ulong, *mut c_ulong) -> Rv,
+    find_objects_final: unsafe extern "C" fn(c_ulong) -> Rv,
+    encrypt_init: unsafe extern "C" fn(c_ulong, *mut CkMechanism, c_ulong) -> Rv,
+    encrypt: unsafe extern "C" fn(c_ulong, *const u8, c_ulong, *mut u8, *mut c_ulong) -> Rv,
+    _encrypt_update: [usize; 2],
+    decrypt_init: unsafe extern "C" fn(c_ulong, *mut CkMechanism, c_ulong) -> Rv,
+    decrypt: unsafe extern "C" fn(c_ulong, *const u8, c_ulong, *mut u8, *mut c_ulong) -> Rv,
+    _decrypt_update: [usize; 27],
+    derive_key: unsafe extern "C" fn(
+        c_ulong,
+        *mut CkMechanism,
+        c_ulong,
+        *mut CkAttribute,
+        c_ulong,
+        *mut c_ulong,
+    ) -> Rv,
+}
+
+fn check(rv: Rv, operation: &str) -> Result<()> {
+    match rv {
+        CKR_OK => Ok(()),
+        CKR_PIN_INCORRECT => Err(anyhow!("Incorrect token PIN")),
+        CKR_PIN_LOCKED => Err(anyhow!("The token PIN is locked")),
+        rv => Err(anyhow!("PKCS#11 {} failed with {:#x}", operation, rv)),
+    }
+}
+
+struct Module {
+    functions: *const CkFunctionList,
+    path: PathBuf,
+    // Another user of the module in this process initialized it, so it must not be finalized
+    finalize: AtomicBool,
+    _library: libloading::Library,
+}
+
+// The module is initialized with CKF_OS_LOCKING_OK, so it can be used from multiple threads
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+impl Module {
+    /// Load and initialize the module at `path`, or share it if this process already loaded it.
+    fn load(path: &Path) -> Result<Arc<Self>> {
+        let path = path.canonicalize().unwrap_or(path.to_owned());
+        let mut modules = MODULES.lock().unwrap();
+        let modules = modules.get_or_insert_with(HashMap::new);
+        if let Some(module) = modules.get(&path).and_then(Weak::upgrade) {
+            return Ok(module);
+        }
+
+        let module = Arc::new(Self::initialize(path.clone())?);
+        modules.retain(|_, module| module.strong_count() > 0);
+        modules.insert(path, Arc::downgrade(&module));
+        Ok(module)
+    }
+
+    fn initialize(path: PathBuf) -> Result<Self> {
+        let library = unsafe { libloading::Library::new(&path) }
+            .map_err(|e| anyhow!("Failed to load PKCS#11 module {}: {}", path.display(), e))?;
+
+        let mut functions: *const CkFunctionList = ptr::null();
+        unsafe {
+            let get_function_list = library.get::<unsafe extern "C" fn(
+                *mut *const CkFunctionList,
+            ) -> Rv>(b"C_GetFunctionList\0")?;
+            check(get_function_list(&mut functions), "C_GetFunctionList")?;
+        }
+        if functions.is_null() {
+            bail!("PKCS#11 module returned no function list");
+        }
+
+        let mut args = CkInitializeArgs {
+            create_mutex: ptr::null_mut(),
+            destroy_mutex: ptr::null_mut(),
+            lock_mutex: ptr::null_mut(),
+            unlock_mutex: ptr::null_mut(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        let rv = unsafe { ((*functions).initialize)(&mut args as *mut _ as *mut c_void) };
+        let finalize = rv != CKR_CRYPTOKI_ALREADY_INITIALIZED;
+        if finalize {
+            check(rv, "C_Initialize")?;
+        }
+
+        Ok(Module {
+            functions,
+            path,
+            finalize: AtomicBool::new(finalize),
+            _library: library,
+        })
+    }
+
+    fn f(&self) -> &CkFunctionList {
+        unsafe { &*self.functions }
+    }
+
+    /// Slots with a token present.
+    fn slots(&self) -> Result<Vec<c_ulong>> {
+        let mut count: c_ulong = 0;
+        check(
+            unsafe { (self.f().get_slot_list)(1, ptr::null_mut(), &mut count) },
+            "C_GetSlotList",
+        )?;
+        let mut slots = vec![0; count as usize];
+        check(
+            unsafe { (self.f().get_slot_list)(1, slots.as_mut_ptr(), &mut count) },
+            "C_GetSlotList",
+        )?;
+        slots.truncate(count as usize);
+        Ok(slots)
+    }
+
+    fn token_info(&self, slot: c_ulong) -> Result<TokenInfo> {
+        let mut info: CkTokenInfo = unsafe { std::mem::zeroed() };
+        check(
+            unsafe { (self.f().get_token_info)(slot, &mut info) },
+            "C_GetTokenInfo",
+        )?;
+        Ok(TokenInfo {
+            label: padded_string(&info.label),
+            manufacturer: padded_string(&info.manufacturer_id),
+            model: padded_string(&info.model),
+            serial: padded_string(&info.serial_number),
+            protected_authentication_path: info.flags & CKF_PROTECTED_AUTHENTICATION_PATH != 0,
+        })
+    }
+
+    fn find_slot(&self, token_serial: &str) -> Result<c_ulong> {
+        for slot in self.slots()? {
+            if self.token_info(slot)?.serial == token_serial {
+                return Ok(slot);
+            }
+        }
+        bail!("No token with serial {} found", token_serial)
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        // Sessions hold a reference, so none is open by now
+        let modules = MODULES.lock().unwrap();
+        let finalize = self.finalize.load(Ordering::SeqCst);
+
+        // A load racing with this drop found the module already initialized, hand finalizing it
+        // over to the newer instance
+        let newer = modules
+            .as_ref()
+            .and_then(|modules| modules.get(&self.path))
+            .and_then(Weak::upgrade);
+        if let Some(newer) = newer {
+            newer.finalize.fetch_or(finalize, Ordering::SeqCst);
+            // Unlocked first, in case this was the last reference and its drop needs the lock
+            drop(modules);
+            return;
+        }
+
+        if finalize {
+            unsafe { (self.f().finalize)(ptr::null_mut()) };
+        }
+    }
+}
+
+struct Session {
+    module: Arc<Module>,
+    handle: c_ulong,
+    logged_in: bool,
+}
+
+impl Session {
+    fn open(module: &Arc<Module>, slot: c_ulong) -> Result<Self> {
+        let mut handle: c_ulong = 0;
+        check(
+            unsafe {
+                (module.f().open_session)(
+                    slot,
+                    CKF_SERIAL_SESSION,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    &mut handle,
+                )
+            },
+            "C_OpenSession",
+        )?;
+        Ok(Session {
+            module: module.clone(),
+            handle,
+            logged_in: false,
+        })
+    }
+
+    fn f(&self) -> &CkFunctionList {
+        self.module.f()
+    }
+
+    fn login(&mut self, pin: Option<&str>) -> Result<()> {
+        let (pin_ptr, pin_len) = match pin {
+            Some(pin) => (pin.as_ptr(), pin.len() as c_ulong),
+            None => (ptr::null(), 0),
+        };
+        let rv = unsafe { (self.f().login)(self.handle, CKU_USER, pin_ptr, pin_len) };
+        if rv != CKR_USER_ALREADY_LOGGED_IN {
+            check(rv, "C_Login")?;
+        }
+        self.logged_in = true;
+        Ok(())
+    }
+
+    fn find_objects(&self, template: &[Attribute]) -> Result<Vec<c_ulong>> {
+        let mut template: Vec<CkAttribute> = template.iter().map(Attribute::as_ck).collect();
+        check(
+            unsafe {
+                (self.f().find_objects_init)(
+                    self.handle,
+                    template.as_mut_ptr(),
+                    template.len() as c_ulong,
+                )
+            },
+            "C_FindObjectsInit",
+        )?;
+
+        let mut objects = Vec::new();
+        let result = loop {
+            let mut batch = [0 as c_ulong; 16];
+            let mut count: c_ulong = 0;
+            let rv = unsafe {
+                (self.f().find_objects)(
+                    self.handle,
+                    batch.as_mut_ptr(),
+                    batch.len() as c_ulong,
+                    &mut count,
+                )
+            };
+            if let Err(e) = check(rv, "C_FindObjects") {
+                break Err(e);
+            }
+            if count == 0 {
+                break Ok(objects);
+            }
+            objects.extend_from_slice(&batch[..count as usize]);
+        };
+
+        unsafe { (self.f().find_objects_final)(self.handle) };
+        result
+    }
+
+    fn find_key(&self, key_id: &[u8], class: c_ulong) -> Result<c_ulong> {
+        self.find_objects(&[
+            Attribute::ulong(CKA_CLASS, class),
+            Attribute::bytes(CKA_ID, key_id),
+        ])?
+        .first()
+        .copied()
+        .ok_or(anyhow!("No PKCS#11 key with the configured id found"))
+    }
+
+    fn get_bytes(&self, object: c_ulong, attribute_type: c_ulong) -> Result<Vec<u8>> {
+        // The first call returns the length of the value
+        let mut attribute = CkAttribute {
+            attribute_type,
+            value: ptr::null_mut(),
+            value_len: 0,
+        };
+        check(
+            unsafe { (self.f().get_attribute_value)(self.handle, object, &mut attribute, 1) },
+            "C_GetAttributeValue",
+        )?;
+
+        let mut value = vec![0u8; attribute.value_len as usize];
+        attribute.value = value.as_mut_ptr() as *mut c_void;
+        check(
+            unsafe { (self.f().get_attribute_value)(self.handle, object, &mut attribute, 1) },
+            "C_GetAttributeValue",
+        )?;
+        value.truncate(attribute.value_len as usize);
+        Ok(value)
+    }
+
+    fn get_ulong(&self, object: c_ulong, attribute_type: c_ulong) -> Result<c_ulong> {
+        let value = self.get_bytes(object, attribute_type)?;
+        Ok(c_ulong::from_ne_bytes(
+            value
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("Invalid PKCS#11 attribute {:#x}", attribute_type))?,
+        ))
+    }
+
+    fn encrypt_rsa_oaep(&self, key: c_ulong, data: &[u8]) -> Result<Vec<u8>> {
+        let mut params = oaep_params();
+        let mut mechanism = mechanism(CKM_RSA_PKCS_OAEP, &mut params);
+        check(
+            unsafe { (self.f().encrypt_init)(self.handle, &mut mechanism, key) },
+            "C_EncryptInit",
+        )?;
+        self.single_part(data, self.f().encrypt, "C_Encrypt")
+    }
+
+    fn decrypt_rsa_oaep(&self, key: c_ulong, data: &[u8]) -> Result<Vec<u8>> {
+        let mut params = oaep_params();
+        let mut mechanism = mechanism(CKM_RSA_PKCS_OAEP, &mut params);
+        check(
+            unsafe { (self.f().decrypt_init)(self.handle, &mut mechanism, key) },
+            "C_DecryptInit",
+        )?;
+        self.single_part(data, self.f().decrypt, "C_Decrypt")
+    }
+
+    // Runs C_Encrypt or C_Decrypt, the first call with a null buffer returns the output length
+    fn single_part(
+        &self,
+        data: &[u8],
+        function: unsafe extern "C" fn(c_ulong, *const u8, c_ulong, *mut u8, *mut c_ulong) -> Rv,
+        operation: &str,
+    ) -> Result<Vec<u8>> {
+        let mut len: c_ulong = 0;
+        check(
+            unsafe {
+                function(
+                    self.handle,
+                    data.as_ptr(),
+                    data.len() as c_ulong,
+                    ptr::null_mut(),
+                    &mut len,
+                )
+            },
+            operation,
+        )?;
+        let mut output = vec![0u8; len as usize];
+        check(
+            unsafe {
+                function(
+                    self.handle,
+                    data.as_ptr(),
+                    data.len() as c_ulong,
+                    output.as_mut_ptr(),
+                    &mut len,
+                )
+            },
+            operation,
+        )?;
+        output.truncate(len as usize);
+        Ok(output)
+    }
+
+    /// Agree on a secret with `public_point` and the private `key`, returning the raw X coordinate.
+    fn derive_ecdh(&self, key: c_ulong, public_point: &[u8]) -> Result<Vec<u8>> {
+        let mut public_point = public_point.to_vec();
+        let mut params = CkEcdh1DeriveParams {
+            kdf: CKD_NULL,
+            shared_data_len: 0,
+            shared_data: ptr::null_mut(),
+            public_data_len: public_point.len() as c_ulong,
+            public_data: public_point.as_mut_ptr(),
+        };
+        let mut mechanism = mechanism(CKM_ECDH1_DERIVE, &mut params);
+
+        // A session object the value can be read from, destroyed right after
+        let template = [
+            Attribute::ulong(CKA_CLASS, CKO_SECRET_KEY),
+            Attribute::ulong(CKA_KEY_TYPE, CKK_GENERIC_SECRET),
+            Attribute::ulong(CKA_VALUE_LEN, 32),
+            Attribute::bool(CKA_TOKEN, false),
+            Attribute::bool(CKA_SENSITIVE, false),
+            Attribute::bool(CKA_EXTRACTABLE, true),
+        ];
+        let mut template: Vec<CkAttribute> = template.iter().map(Attribute::as_ck).collect();
+
+        let mut derived: c_ulong = 0;
+        check(
+            unsafe {
+                (self.f().derive_key)(
+                    self.handle,
+                    &mut mechanism,
+                    key,
+                    template.as_mut_ptr(),
+                    template.len() as c_ulong,
+                    &mut derived,
+                )
+            },
+            "C_DeriveKey",
+        )?;
+
+        let value = self.get_bytes(derived, CKA_VALUE);
+        unsafe { (self.f().destroy_object)(self.handle, derived) };
+        value
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe {
+            if self.logged_in {
+                (self.f().logout)(self.handle);
+            }
+            (self.f().close_session)(self.handle);
+        }
+    }
+}
+
+/// An attribute value owned on the Rust side, borrowed by the CK_ATTRIBUTE passed to the module.
+struct Attribute {
+    attribute_type: c_ulong,
+    value: Vec<u8>,
+}
+
+impl Attribute {
+    fn bytes(attribute_type: c_ulong, value: &[u8]) -> Self {
+        Attribute {
+            attribute_type,
+            value: value.to_vec(),
+        }
+    }
+
+    fn ulong(attribute_type: c_ulong, value: c_ulong) -> Self {
+        Self::bytes(attribute_type, &value.to_ne_bytes())
+    }
+
+    fn bool(attribute_type: c_ulong, value: bool) -> Self {
+        Self::bytes(attribute_type, &[value as u8])
+    }
+
+    fn as_ck(&self) -> CkAttribute {
+        CkAttribute {
+            attribute_type: self.attribute_type,
+            value: self.value.as_ptr() as *mut c_void,
+            value_len: self.value.len() as c_ulong,
+        }
+    }
+}
+
+fn oaep_params() -> CkRsaPkcsOaepParams {
+    CkRsaPkcsOaepParams {
+        hash_alg: CKM_SHA256,
+        mgf: CKG_MGF1_SHA256,
+        source: CKZ_DATA_SPECIFIED,
+        source_data: ptr::null_mut(),
+        source_data_len: 0,
+    }
+}
+
+fn mechanism<T>(mechanism: c_ulong, params: &mut T) -> CkMechanism {
+    CkMechanism {
+        mechanism,
+        parameter: params as *mut T as *mut c_void,
+        parameter_len: std::mem::size_of::<T>() as c_ulong,
+    }
+}
+
+// Token info strings are padded with spaces instead of null terminated
+fn padded_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).trim_end().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn wrapped_key_roundtrip() {
+        let rsa = WrappedKey::RsaOaep(vec![1, 2, 3]);
+        assert_eq!(rsa.to_string().parse::<WrappedKey>().unwrap(), rsa);
+
+        let ec = WrappedKey::EcdhP256 {
+            ephemeral: vec![4, 5, 6],
+            secret: "2.AAAA|BBBB|CCCC".to_owned(),
+        };
+        assert_eq!(ec.to_string().parse::<WrappedKey>().unwrap(), ec);
+
+        assert!("aes:AQID".parse::<WrappedKey>().is_err());
+    }
+
+    #[test]
+    fn config_roundtrip() {
+        let config = Pkcs11Config {
+            module: PathBuf::from("/usr/lib/pkcs11/opensc|pkcs11.so"),
+            token_serial: "1234|5678".to_owned(),
+            key_id: vec![0x01, 0x02],
+        };
+        assert_eq!(config.to_string().parse::<Pkcs11Config>().unwrap(), config);
+
+        assert!("AQID|AQID".parse::<Pkcs11Config>().is_err());
+    }
+
+    #[test]
+    fn ecdh_wrap_and_unwrap() {
+        // Stands in for the token key, the token computes the same raw shared secret
+        let token_key = SecretKey::random(&mut rand::rngs::OsRng);
+
+        let wrapped = wrap_ecdh(&token_key.public_key(), b"os key part").unwrap();
+        let WrappedKey::EcdhP256 { ephemeral, secret } = wrapped else {
+            panic!("Expected an ECDH wrapped key");
+        };
+
+        let shared = p256::ecdh::diffie_hellman(
+            token_key.to_nonzero_scalar(),
+            PublicKey::from_sec1_bytes(&ephemeral).unwrap().as_affine(),
+        );
+        let unwrapped = unwrap_ecdh(shared.raw_secret_bytes(), &ephemeral, &secret).unwrap();
+        assert_eq!(unwrapped.as_bytes(), b"os key part");
+
+        // A different token key cannot unwrap it
+        let other = SecretKey::random(&mut rand::rngs::OsRng);
+        let shared = p256::ecdh::diffie_hellman(
+            other.to_nonzero_scalar(),
+            PublicKey::from_sec1_bytes(&ephemeral).unwrap().as_affine(),
+        );
+        assert!(unwrap_ecdh(shared.raw_secret_bytes(), &ephemeral, &secret).is_err());
+    }
+
+    #[test]
+    fn decode_der_and_raw_ec_points() {
+        let mut point = vec![0x04];
+        point.extend_from_slice(&[7u8; 64]);
+
+        let mut der = vec![0x04, 65];
+        der.extend_from_slice(&point);
+
+        assert_eq!(decode_ec_point(&der), &point[..]);
+        assert_eq!(decode_ec_point(&point), &point[..]);
+    }
+
+    #[test]
+    fn rejects_other_curves() {
+        assert!(check_p256(P256_OID).is_ok());
+        // secp384r1
+        assert!(check_p256(&[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22]).is_err());
+    }
+
+    // Requires SoftHSM2 with a token labelled `bitwarden-test`, PIN 1234 and key pairs with the ids
+    // 01 (RSA) and 02 (EC P-256), e.g.
+    //   softhsm2-util --init-token --free --label bitwarden-test --pin 1234 --so-pin 1234
+    //   pkcs11-tool --module $SOFTHSM2_MODULE --login --pin 1234 --keypairgen --key-type rsa:2048 --id 01
+    //   pkcs11-tool --module $SOFTHSM2_MODULE --login --pin 1234 --keypairgen --key-type EC:prime256v1 --id 02
+    #[cfg(feature = "manual_test")]
+    fn softhsm_path() -> PathBuf {
+        std::env::var("SOFTHSM2_MODULE")
+            .unwrap_or("/usr/lib/softhsm/libsofthsm2.so".to_owned())
+            .into()
+    }
+
+    #[cfg(feature = "manual_test")]
+    fn softhsm() -> (Arc<Module>, c_ulong) {
+        let module = Module::load(&softhsm_path()).unwrap();
+        let slot = module
+            .slots()
+            .unwrap()
+            .into_iter()
+            .find(|slot| module.token_info(*slot).unwrap().label == "bitwarden-test")
+            .unwrap();
+        (module, slot)
+    }
+
+    #[cfg(feature = "manual_test")]
+    fn softhsm_wrap_unwrap(key_id: &[u8]) {
+        let (module, slot) = softhsm();
+
+        let wrapped = wrap(&Session::open(&module, slot).unwrap(), key_id, b"secret").unwrap();
+
+        let mut session = Session::open(&module, slot).unwrap();
+        assert!(unwrap(&session, key_id, &wrapped).is_err());
+        session.login(Some("1234")).unwrap();
+        assert_eq!(
+            unwrap(&session, key_id, &wrapped).unwrap().as_bytes(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "manual_test")]
+    fn softhsm_rsa_wrap_unwrap() {
+        softhsm_wrap_unwrap(&[0x01]);
+    }
+
+    #[test]
+    #[cfg(feature = "manual_test")]
+    fn softhsm_ec_wrap_unwrap() {
+        softhsm_wrap_unwrap(&[0x02]);
+    }
+
+    #[test]
+    #[cfg(feature = "manual_test")]
+    fn softhsm_wrong_pin() {
+        let (module, slot) = softhsm();
+        let mut session = Session::open(&module, slot).unwrap();
+        assert_eq!(
+            session.login(Some("0000")).unwrap_err().to_string(),
+            "Incorrect token PIN"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "manual_test")]
+    fn softhsm_listing_keeps_open_sessions() {
+        let (module, slot) = softhsm();
+        let mut session = Session::open(&module, slot).unwrap();
+        session.login(Some("1234")).unwrap();
+
+        // Shares the loaded module instead of finalizing it once done
+        assert!(!list_tokens(&softhsm_path()).unwrap().is_empty());
+        assert!(Arc::ptr_eq(
+            &module,
+            &Module::load(&softhsm_path()).unwrap()
+        ));
+
+        let wrapped = wrap(&session, &[0x01], b"secret").unwrap();
+        assert_eq!(
+            unwrap(&session, &[0x01], &wrapped).unwrap().as_bytes(),
+            b"secret"
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use std::{path::PathBuf, process::Command};
+
+use anyhow::{anyhow, bail, Result};
+
+/// The polkit action used to gate biometric unlock.
+pub const POLICY_ACTION_ID: &str = "com.bitwarden.Bitwarden.unlock";
+
+const ACTIONS_DIR: &str = "/usr/share/polkit-1/actions";
+const RULES_DIR: &str = "/etc/polkit-1/rules.d";
+const RULES_FILE_NAME: &str = "50-com.bitwarden.Bitwarden.unlock.rules";
+
+// Installs all files in a single privileged call, so the user is only prompted once. The first
+// argument is a stale rules file to remove, followed by `source destination` pairs.
+const INSTALL_SCRIPT: &str = r#"set -e
+rm -f "$1"
+shift
+while [ "$#" -gt 0 ]; do
+    install -D -m 0644 "$1" "$2"
+    shift 2
+done"#;
+
+/// Implicit authorization granted by polkit, see `man polkit` for the exact semantics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthDefault {
+    No,
+    Yes,
+    AuthSelf,
+    AuthSelfKeep,
+    AuthAdmin,
+    AuthAdminKeep,
+}
+
+impl AuthDefault {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuthDefault::No => "no",
+            AuthDefault::Yes => "yes",
+            AuthDefault::AuthSelf => "auth_self",
+            AuthDefault::AuthSelfKeep => "auth_self_keep",
+            AuthDefault::AuthAdmin => "auth_admin",
+            AuthDefault::AuthAdminKeep => "auth_admin_keep",
+        }
+    }
+}
+
+pub struct Policy {
+    pub description: String,
+    /// The message shown in the authentication dialog.
+    pub message: String,
+    /// Translated messages as `(language, message)` pairs, e.g. `("de", "...")`.
+    pub localized_messages: Vec<(String, String)>,
+    pub allow_any: AuthDefault,
+    pub allow_inactive: AuthDefault,
+    pub allow_active: AuthDefault,
+    /// Restricts the action to the given unix users. An empty list allows every user.
+    pub allowed_users: Vec<String>,
+    /// The uid declared as the action owner. polkit only accepts details, such as the dialog
+    /// message, from the owner or root.
+    pub owner: Option<u32>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            description: "Unlock Bitwarden".to_owned(),
+            message: "Authenticate to unlock Bitwarden".to_owned(),
+            localized_messages: Vec::new(),
+            allow_any: AuthDefault::No,
+            allow_inactive: AuthDefault::No,
+            allow_active: AuthDefault::AuthSelf,
+            allowed_users: Vec::new(),
+            owner: Some(unsafe { libc::getuid() }),
+        }
+    }
+}
+
+/// Render the polkit action definition for [POLICY_ACTION_ID].
+pub fn render_policy(policy: &Policy) -> String {
+    let mut messages = format!("    <message>{}</message>\n", escape_xml(&policy.message));
+    for (lang, message) in &policy.localized_messages {
+        messages.push_str(&format!(
+            "    <message xml:lang=\"{}\">{}</message>\n",
+            escape_xml(lang),
+            escape_xml(message)
+        ));
+    }
+
+    let annotations = match policy.owner {
+        Some(owner) => format!(
+            "    <annotate key=\"org.freedesktop.policykit.owner\">unix-user:{}</annotate>\n",
+            owner
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE policyconfig PUBLIC
+ "-//freedesktop//DTD PolicyKit Policy Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/PolicyKit/1.0/policyconfig.dtd">
+<policyconfig>
+  <vendor>Bitwarden</vendor>
+  <vendor_url>https://bitwarden.com</vendor_url>
+  <action id="{action_id}">
+    <description>{description}</description>
+{messages}    <defaults>
+      <allow_any>{allow_any}</allow_any>
+      <allow_inactive>{allow_inactive}</allow_inactive>
+      <allow_active>{allow_active}</allow_active>
+    </defaults>
+{annotations}  </action>
+</policyconfig>
+"#,
+        action_id = POLICY_ACTION_ID,
+        description = escape_xml(&policy.description),
+        allow_any = policy.allow_any.as_str(),
+        allow_inactive = policy.allow_inactive.as_str(),
+        allow_active = policy.allow_active.as_str(),
+    )
+}
+
+/// Render a polkit rule that denies [POLICY_ACTION_ID] to everyone not in `allowed_users`.
+///
+/// Action definitions cannot be restricted to users, so this is done through a rules file.
+/// Returns `None` if no restriction is configured.
+pub fn render_rules(policy: &Policy) -> Result<Option<String>> {
+    if policy.allowed_users.is_empty() {
+        return Ok(None);
+    }
+
+    let mut users = Vec::new();
+    for user in &policy.allowed_users {
+        if !is_valid_user_name(user) {
+            bail!("Invalid user name: {}", user);
+        }
+        users.push(format!("\"{}\"", user));
+    }
+
+    Ok(Some(format!(
+        r#"polkit.addRule(function(action, subject) {{
+    if (action.id == "{}" && [{}].indexOf(subject.user) < 0) {{
+        return polkit.Result.NO;
+    }}
+}});
+"#,
+        POLICY_ACTION_ID,
+        users.join(", ")
+    )))
+}
+
+/// Install the policy system wide. Prompts for administrator credentials using `pkexec`.
+pub fn install_policy(policy: &Policy) -> Result<()> {
+    Installer::system().install(policy)
+}
+
+/// Remove a policy previously installed with [install_policy].
+pub fn uninstall_policy() -> Result<()> {
+    Installer::system().uninstall()
+}
+
+pub fn is_policy_installed() -> bool {
+    Installer::system().policy_path().exists()
+}
+
+struct Installer {
+    actions_dir: PathBuf,
+    rules_dir: PathBuf,
+    elevate: bool,
+}
+
+impl Installer {
+    fn system() -> Self {
+        Installer {
+            actions_dir: PathBuf::from(ACTIONS_DIR),
+            rules_dir: PathBuf::from(RULES_DIR),
+            elevate: true,
+        }
+    }
+
+    fn policy_path(&self) -> PathBuf {
+        self.actions_dir.join(format!("{}.policy", POLICY_ACTION_ID))
+    }
+
+    fn rules_path(&self) -> PathBuf {
+        self.rules_dir.join(RULES_FILE_NAME)
+    }
+
+    fn install(&self, policy: &Policy) -> Result<()> {
+        let rules = render_rules(policy)?;
+
+        let staging = tempfile::tempdir()?;
+
+        let staged_policy = staging.path().join("policy");
+        std::fs::write(&staged_policy, render_policy(policy))?;
+
+        let mut command = self.command("/bin/sh");
+        command
+            .arg("-c")
+            .arg(INSTALL_SCRIPT)
+            .arg("sh")
+            .arg(self.rules_path())
+            .arg(&staged_policy)
+            .arg(self.policy_path());
+
+        if let Some(rules) = rules {
+            let staged_rules = staging.path().join("rules");
+            std::fs::write(&staged_rules, rules)?;
+            command.arg(&staged_rules).arg(self.rules_path());
+        }
+
+        run(command)
+    }
+
+    fn uninstall(&self) -> Result<()> {
+        let mut command = self.command("/bin/rm");
+        command
+            .arg("-f")
+            .arg(self.policy_path())
+            .arg(self.rules_path());
+
+        run(command)
+    }
+
+    fn command(&self, program: &str) -> Command {
+        if self.elevate {
+            let mut command = Command::new("pkexec");
+            command.arg(program);
+            command
+        } else {
+            Command::new(program)
+        }
+    }
+}
+
+fn run(mut command: Command) -> Result<()> {
+    let output = command.output()?;
+
+    match output.status.code() {
+        Some(0) => Ok(()),
+        // pkexec uses 126 when the dialog was dismissed and 127 when authorization failed
+        Some(126) | Some(127) => Err(anyhow!("Not authorized to install the polkit policy")),
+        _ => Err(anyhow!(
+            "Failed to install the polkit policy: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+fn is_valid_user_name(user: &str) -> bool {
+    !user.is_empty()
+        && user
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '$'))
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn installer_for(root: &Path) -> Installer {
+        Installer {
+            actions_dir: root.join("actions"),
+            rules_dir: root.join("rules.d"),
+            elevate: false,
+        }
+    }
+
+    fn child_text<'a>(node: roxmltree::Node<'a, 'a>, name: &str) -> Option<&'a str> {
+        node.children()
+            .find(|n| n.has_tag_name(name))
+            .and_then(|n| n.text())
+    }
+
+    #[test]
+    fn render_policy_is_valid_xml() {
+        let policy = Policy {
+            message: "Unlock <Bitwarden> & more".to_owned(),
+            localized_messages: vec![("de".to_owned(), "Bitwarden entsperren".to_owned())],
+            allow_active: AuthDefault::AuthAdminKeep,
+            ..Default::default()
+        };
+
+        let xml = render_policy(&policy);
+        let document = roxmltree::Document::parse_with_options(
+            &xml,
+            roxmltree::ParsingOptions {
+                allow_dtd: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let action = document
+            .descendants()
+            .find(|n| n.has_tag_name("action"))
+            .unwrap();
+        assert_eq!(action.attribute("id"), Some(POLICY_ACTION_ID));
+
+        let messages: Vec<_> = action
+            .children()
+            .filter(|n| n.has_tag_name("message"))
+            .map(|n| {
+                (
+                    n.attribute(("http://www.w3.org/XML/1998/namespace", "lang")),
+                    n.text().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (None, "Unlock <Bitwarden> & more"),
+                (Some("de"), "Bitwarden entsperren")
+            ]
+        );
+
+        let defaults = action
+            .children()
+            .find(|n| n.has_tag_name("defaults"))
+            .unwrap();
+        assert_eq!(child_text(defaults, "allow_any"), Some("no"));
+        assert_eq!(child_text(defaults, "allow_inactive"), Some("no"));
+        assert_eq!(child_text(defaults, "allow_active"), Some("auth_admin_keep"));
+
+        let owner = action
+            .children()
+            .find(|n| n.attribute("key") == Some("org.freedesktop.policykit.owner"))
+            .unwrap();
+        let expected = format!("unix-user:{}", unsafe { libc::getuid() });
+        assert_eq!(owner.text(), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn render_policy_without_owner() {
+        let policy = Policy {
+            owner: None,
+            ..Default::default()
+        };
+
+        assert!(!render_policy(&policy).contains("org.freedesktop.policykit.owner"));
+    }
+
+    #[test]
+    fn render_rules_without_users() {
+        assert!(render_rules(&Policy::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn render_rules_with_users() {
+        let policy = Policy {
+            allowed_users: vec!["alice".to_owned(), "bob".to_owned()],
+            ..Default::default()
+        };
+
+        let rules = render_rules(&policy).unwrap().unwrap();
+        assert!(rules.contains(POLICY_ACTION_ID));
+        assert!(rules.contains(r#"["alice", "bob"]"#));
+    }
+
+    #[test]
+    fn render_rules_rejects_invalid_users() {
+        let policy = Policy {
+            allowed_users: vec!["alice\"); evil(); //".to_owned()],
+            ..Default::default()
+        };
+
+        assert!(render_rules(&policy).is_err());
+    }
+
+    #[test]
+    fn install_and_uninstall() {
+        let root = tempfile::tempdir().unwrap();
+        let installer = installer_for(root.path());
+        let policy = Policy {
+            allowed_users: vec!["alice".to_owned()],
+            ..Default::default()
+        };
+
+        installer.install(&policy).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(installer.policy_path()).unwrap(),
+            render_policy(&policy)
+        );
+        assert_eq!(
+            std::fs::read_to_string(installer.rules_path()).unwrap(),
+            render_rules(&policy).unwrap().unwrap()
+        );
+
+        // Reinstalling without a user restriction removes the stale rules file
+        installer.install(&Policy::default()).unwrap();
+        assert!(installer.policy_path().exists());
+        assert!(!installer.rules_path().exists());
+
+        installer.uninstall().unwrap();
+        assert!(!installer.policy_path().exists());
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use rand::RngCore;
+
+use crate::secret::SecretBuf;
+
+// The sealed OS key part is stored next to the biometric secrets. It can only be unsealed by the
+// TPM it was created on, so a copied keyring entry is useless on another machine.
+const SEALED_KEY_SERVICE: &str = "Bitwarden_biometric";
+const SEALED_KEY_ACCOUNT: &str = "tpm_sealed_os_key_part";
+
+// Set by `enable` until the sealed key part is created. Afterwards the sealed key part, which
+// records its PCR selection, persists the setting across restarts.
+static CONFIG: Mutex<Option<TpmConfig>> = Mutex::new(None);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TpmConfig {
+    /// Binds the sealed key to the given PCR values, e.g. `sha256:0,7`. The key can then no longer
+    /// be unsealed if the firmware or secure boot state changes.
+    pub pcrs: Option<String>,
+}
+
+/// Seal the biometric OS key part to the TPM. Applies to keys derived after this call.
+pub fn enable(config: TpmConfig) -> Result<()> {
+    if let Some(pcrs) = &config.pcrs {
+        validate_pcrs(pcrs)?;
+    }
+    *CONFIG.lock().unwrap() = Some(config);
+    Ok(())
+}
+
+/// Stop sealing the OS key part and remove the sealed key part, so keys derived afterwards no
+/// longer depend on the TPM.
+pub fn disable() -> Result<()> {
+    *CONFIG.lock().unwrap() = None;
+    delete_os_key_part()
+}
+
+/// The sealing setting, if enabled in this process or by a sealed key part stored before.
+pub fn config() -> Result<Option<TpmConfig>> {
+    if let Some(config) = CONFIG.lock().unwrap().clone() {
+        return Ok(Some(config));
+    }
+
+    match crate::password::find_password(SEALED_KEY_SERVICE, SEALED_KEY_ACCOUNT)? {
+        Some(sealed) => {
+            let sealed: SealedKey = sealed.as_str()?.parse()?;
+            Ok(Some(TpmConfig { pcrs: sealed.pcrs }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Check if a TPM 2.0 is accessible through `tpm2-tools`.
+pub fn is_available() -> bool {
+    Command::new("tpm2_getcap")
+        .arg("properties-fixed")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Returns the random OS key part sealed to the TPM, creating and storing it on first use.
+///
+/// An existing key part is only unsealed if `verified` indicates the user passed a biometric
+/// prompt. A key part is only created if none is stored, a keyring that cannot be read fails
+/// instead of replacing the key part other secrets depend on.
+pub fn os_key_part(config: &TpmConfig, verified: bool) -> Result<SecretBuf> {
+    match crate::password::find_password(SEALED_KEY_SERVICE, SEALED_KEY_ACCOUNT)? {
+        Some(sealed) => {
+            if !verified {
+                bail!("Biometric verification is required to unseal the TPM key");
+            }
+            let sealed: SealedKey = sealed.as_str()?.parse()?;
+            if sealed.pcrs != config.pcrs {
+                bail!("The TPM sealed key uses a different PCR policy, re-enroll biometrics");
+            }
+            unseal(&sealed)
+        }
+        None => {
+            let mut key_part = SecretBuf::new(32)?;
+            rand::thread_rng().fill_bytes(key_part.as_mut_bytes());
+
+            let sealed = seal(&key_part, config.pcrs.as_deref())?;
+            crate::password::set_password(
+                SEALED_KEY_SERVICE,
+                SEALED_KEY_ACCOUNT,
+                &sealed.to_string(),
+            )?;
+            Ok(key_part)
+        }
+    }
+}
+
+/// Remove the sealed OS key part, e.g. when biometric unlock is disabled.
+pub fn delete_os_key_part() -> Result<()> {
+    crate::password::delete_password(SEALED_KEY_SERVICE, SEALED_KEY_ACCOUNT)
+}
+
+/// A secret sealed to the TPM, the private part is encrypted by the TPM storage hierarchy.
+#[derive(Debug, PartialEq, Eq)]
+struct SealedKey {
+    pcrs: Option<String>,
+    public: Vec<u8>,
+    private: Vec<u8>,
+}
+
+impl std::fmt::Display for SealedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}|{}|{}",
+            self.pcrs.as_deref().unwrap_or_default(),
+            base64_engine.encode(&self.public),
+            base64_engine.encode(&self.private)
+        )
+    }
+}
+
+impl std::str::FromStr for SealedKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split('|').collect();
+        let [pcrs, public, private] = parts[..] else {
+            bail!("Invalid sealed key, expected 3 parts, got {}", parts.len());
+        };
+
+        Ok(SealedKey {
+            pcrs: (!pcrs.is_empty()).then(|| pcrs.to_owned()),
+            public: base64_engine.decode(public)?,
+            private: base64_engine.decode(private)?,
+        })
+    }
+}
+
+fn seal(secret: &[u8], pcrs: Option<&str>) -> Result<SealedKey> {
+    let dir = tempfile::tempdir()?;
+    create_primary(dir.path())?;
+
+    let mut create = Command::new("tpm2_create");
+    create
+        .current_dir(dir.path())
+        .args(["-Q", "-C", "primary.ctx", "-u", "seal.pub", "-r", "seal.priv", "-i", "-"]);
+
+    if let Some(pcrs) = pcrs {
+        run(Command::new("tpm2_createpolicy")
+            .current_dir(dir.path())
+            .args(["-Q", "--policy-pcr", "-l", pcrs, "-L", "policy.digest"]))?;
+        // Without `userwithauth` the key can only be unsealed by satisfying the PCR policy
+        create.args(["-L", "policy.digest", "-a", "fixedtpm|fixedparent"]);
+    }
+
+    let mut child = create
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("Failed to open tpm2_create stdin"))?
+        .write_all(secret)?;
+    check_output(child.wait_with_output()?)?;
+
+    Ok(SealedKey {
+        pcrs: pcrs.map(str::to_owned),
+        public: std::fs::read(dir.path().join("seal.pub"))?,
+        private: std::fs::read(dir.path().join("seal.priv"))?,
+    })
+}
+
+fn unseal(sealed: &SealedKey) -> Result<SecretBuf> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("seal.pub"), &sealed.public)?;
+    std::fs::write(dir.path().join("seal.priv"), &sealed.private)?;
+
+    create_primary(dir.path())?;
+    run(Command::new("tpm2_load").current_dir(dir.path()).args([
+        "-Q",
+        "-C",
+        "primary.ctx",
+        "-u",
+        "seal.pub",
+        "-r",
+        "seal.priv",
+        "-c",
+        "seal.ctx",
+    ]))?;
+
+    let mut unseal = Command::new("tpm2_unseal");
+    unseal.current_dir(dir.path()).args(["-c", "seal.ctx"]);
+    if let Some(pcrs) = &sealed.pcrs {
+        unseal.arg("-p").arg(format!("pcr:{}", pcrs));
+    }
+    Ok(SecretBuf::from_vec(run(&mut unseal)?)?)
+}
+
+// The primary key is derived deterministically from the owner hierarchy seed, so it does not
+// need to be persisted.
+fn create_primary(dir: &Path) -> Result<()> {
+    run(Command::new("tpm2_createprimary")
+        .current_dir(dir)
+        .args(["-Q", "-C", "o", "-g", "sha256", "-G", "ecc", "-c", "primary.ctx"]))?;
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<Vec<u8>> {
+    check_output(command.stdin(Stdio::null()).output()?)
+}
+
+fn check_output(output: std::process::Output) -> Result<Vec<u8>> {
+    if !output.status.success() {
+        bail!(
+            "TPM operation failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+fn validate_pcrs(pcrs: &str) -> Result<()> {
+    let valid = pcrs.split('+').all(|bank| match bank.split_once(':') {
+        Some((alg, indices)) => {
+            !alg.is_empty()
+                && alg.chars().all(|c| c.is_ascii_alphanumeric())
+                && !indices.is_empty()
+                && indices
+                    .split(',')
+                    .all(|i| matches!(i.parse::<u8>(), Ok(i) if i < 24))
+        }
+        None => false,
+    });
+
+    if !valid {
+        bail!("Invalid PCR selection: {}", pcrs);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_key_roundtrip() {
+        let sealed = SealedKey {
+            pcrs: Some("sha256:0,7".to_owned()),
+            public: vec![1, 2, 3],
+            private: vec![4, 5, 6],
+        };
+
+        let parsed: SealedKey = sealed.to_string().parse().unwrap();
+        assert_eq!(parsed, sealed);
+    }
+
+    #[test]
+    fn sealed_key_without_pcrs() {
+        let parsed: SealedKey = "|AQID|BAUG".parse().unwrap();
+        assert_eq!(parsed.pcrs, None);
+        assert_eq!(parsed.public, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn sealed_key_rejects_invalid_input() {
+        assert!("AQID|BAUG".parse::<SealedKey>().is_err());
+    }
+
+    #[test]
+    fn validate_pcrs_accepts_selections() {
+        assert!(validate_pcrs("sha256:0,7").is_ok());
+        assert!(validate_pcrs("sha1:0+sha256:7,23").is_ok());
+    }
+
+    #[test]
+    fn validate_pcrs_rejects_invalid_selections() {
+        assert!(validate_pcrs("").is_err());
+        assert!(validate_pcrs("sha256").is_err());
+        assert!(validate_pcrs("sha256:24").is_err());
+        assert!(validate_pcrs("sha256:0;reboot").is_err());
+    }
+
+    // Requires a TPM, e.g. swtpm with `TPM2TOOLS_TCTI=swtpm:port=2321`, enabled by `tpm_test`
+    #[test]
+    #[cfg(feature = "tpm_test")]
+    fn seal_unseal() {
+        let sealed = seal(b"secret", None).unwrap();
+        assert_eq!(unseal(&sealed).unwrap().as_bytes(), b"secret");
+    }
+
+    #[test]
+    #[cfg(feature = "tpm_test")]
+    fn seal_unseal_with_pcr_policy() {
+        let sealed = seal(b"secret", Some("sha256:0,7")).unwrap();
+        assert_eq!(unseal(&sealed).unwrap().as_bytes(), b"secret");
+    }
+
+    #[test]
+    #[cfg(feature = "tpm_test")]
+    fn unseal_fails_for_modified_key() {
+        let mut sealed = seal(b"secret", None).unwrap();
+        let last = sealed.private.len() - 1;
+        sealed.private[last] ^= 0xff;
+        assert!(unseal(&sealed).is_err());
+    }
+}
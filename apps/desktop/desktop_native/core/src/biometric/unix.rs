@@ -1,4 +1,11 @@
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use base64::Engine;
@@ -6,47 +13,102 @@ use rand::RngCore;
 use sha2::{Digest, Sha256};
 
 use crate::biometric::{KeyMaterial, OsDerivedKey, base64_engine};
+use crate::secret::SecretBuf;
+use tokio::sync::Notify;
 use zbus::Connection;
 use zbus_polkit::policykit1::*;
 
-use super::{decrypt, encrypt};
+use super::{encrypt, envelope::EnvelopeKey, fprintd, key_cache, pkcs11, read_secret, tpm};
 use anyhow::anyhow;
-use crate::crypto::CipherString;
+
+const CANCELLED_ERROR: &str = "org.freedesktop.PolicyKit1.Error.Cancelled";
+// polkit rejects details from callers that are neither root nor the action owner, e.g. when the
+// installed policy predates the owner annotation
+const UNTRUSTED_DETAILS_ERROR: &str = "Only trusted callers";
+
+// Set by a successful prompt and consumed when unsealing the TPM protected key part for an
+// existing secret
+static VERIFIED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+// A prompt is meant for the unlock that follows it, not for one started later on
+const VERIFICATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The prompt currently shown and the id of its guard, used by `cancel_prompt`
+static PENDING_PROMPT: Mutex<Option<(u64, PendingPrompt)>> = Mutex::new(None);
+static NEXT_PROMPT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone)]
+enum PendingPrompt {
+    Polkit(String),
+    Fprintd(Arc<Notify>),
+}
+
+// Tracks a prompt in `PENDING_PROMPT` until dropped, unless a newer prompt replaced it
+struct PendingPromptGuard(u64);
+
+impl PendingPromptGuard {
+    fn set(prompt: PendingPrompt) -> Self {
+        let id = NEXT_PROMPT_ID.fetch_add(1, Ordering::SeqCst);
+        *PENDING_PROMPT.lock().unwrap() = Some((id, prompt));
+        PendingPromptGuard(id)
+    }
+}
+
+impl Drop for PendingPromptGuard {
+    fn drop(&mut self) {
+        let mut pending = PENDING_PROMPT.lock().unwrap();
+        if matches!(*pending, Some((id, _)) if id == self.0) {
+            *pending = None;
+        }
+    }
+}
 
 /// The Unix implementation of the biometric trait.
 pub struct Biometric {}
 
 impl super::BiometricTrait for Biometric {
-    async fn prompt(_hwnd: Vec<u8>, _message: String) -> Result<bool> {
+    /// Verifies the user with the fingerprint reader if fingers are enrolled in fprintd, otherwise
+    /// falls back to the generic polkit authentication dialog.
+    async fn prompt(_hwnd: Vec<u8>, message: String) -> Result<bool> {
         let connection = Connection::system().await?;
-        let proxy = AuthorityProxy::new(&connection).await?;
-        let subject = Subject::new_for_owner(std::process::id(), None, None)?;
-        let details = std::collections::HashMap::new();
-        let result = proxy.check_authorization(
-            &subject,
-            "com.bitwarden.Bitwarden.unlock",
-            &details,
-            CheckAuthorizationFlags::AllowUserInteraction.into(),
-            "",
-        ).await;
 
-        match result {
-            Ok(result) => {
-                return Ok(result.is_authorized);
+        let verified = if fprintd::has_enrolled_fingers(&connection).await {
+            let cancel = Arc::new(Notify::new());
+            let _pending = PendingPromptGuard::set(PendingPrompt::Fprintd(cancel.clone()));
+            let progress = super::verify_status_listener();
+            fprintd::verify(&connection, &message, &cancel, progress).await?
+        } else {
+            let cancellation_id = format!("bitwarden-{:016x}", rand::thread_rng().next_u64());
+            let _pending = PendingPromptGuard::set(PendingPrompt::Polkit(cancellation_id.clone()));
+            check_authorization(&connection, &message, &cancellation_id).await?
+        };
+
+        *VERIFIED_AT.lock().unwrap() = verified.then(Instant::now);
+        Ok(verified)
+    }
+
+    async fn cancel_prompt() -> Result<()> {
+        let pending = PENDING_PROMPT.lock().unwrap().clone().map(|(_, prompt)| prompt);
+        match pending {
+            Some(PendingPrompt::Polkit(cancellation_id)) => {
+                let connection = Connection::system().await?;
+                cancel_check_authorization(&connection, &cancellation_id).await
             }
-            Err(e) => {
-                println!("polkit biometric error: {:?}", e);
-                return Ok(false);
+            Some(PendingPrompt::Fprintd(cancel)) => {
+                cancel.notify_one();
+                Ok(())
             }
+            None => Ok(()),
         }
     }
 
+    /// Biometric unlock is set up by installing the polkit policy, so it is only available once the
+    /// policy is installed, even if fprintd could verify the user without it.
     async fn available() -> Result<bool> {
         let connection = Connection::system().await?;
         let proxy = AuthorityProxy::new(&connection).await?;
         let res = proxy.enumerate_actions("en").await?;
         for action in res {
-            if action.action_id == "com.bitwarden.Bitwarden.unlock" {
+            if action.action_id == super::polkit::POLICY_ACTION_ID {
                 return Ok(true);
             }
         }
@@ -62,10 +124,29 @@ impl super::BiometricTrait for Biometric {
             None => random_challenge(),
         };
 
-        // there is no windows hello like interactive bio protected secret at the moment on linux
-        // so we use a a key derived from the iv. this key is not intended to add any security
-        // but only a place-holder
-        let key = Sha256::digest(challenge);
+        // there is no windows hello like interactive bio protected secret on linux. unless a
+        // PKCS#11 token or TPM is used, the key is derived from the iv. this key is not intended to
+        // add any security but only a place-holder
+        let key = match (pkcs11::config()?, tpm::config()?) {
+            // Unwrapping requires a token login, which takes the place of the prompt
+            (Some(config), _) => {
+                let mut hasher = Sha256::new();
+                hasher.update(pkcs11::os_key_part(&config)?);
+                hasher.update(challenge);
+                hasher.finalize()
+            }
+            (None, Some(config)) => {
+                // A random challenge derives a key no stored secret is encrypted with, e.g. when
+                // enrolling or rotating, so only the key of an existing secret needs a prompt. Each
+                // prompt releases the sealed key part for one such derivation.
+                let verified = challenge_str.is_none() || take_verification();
+                let mut hasher = Sha256::new();
+                hasher.update(tpm::os_key_part(&config, verified)?);
+                hasher.update(challenge);
+                hasher.finalize()
+            }
+            (None, None) => Sha256::digest(challenge),
+        };
         let key_b64 = base64_engine.encode(&key);
         let iv_b64 = base64_engine.encode(&challenge);
         Ok(OsDerivedKey { key_b64, iv_b64 })
@@ -82,28 +163,446 @@ impl super::BiometricTrait for Biometric {
             "Key material is required for polkit protected keys"
         ))?;
 
-        let encrypted_secret = encrypt(secret, &key_material, iv_b64)?;
+        let key = EnvelopeKey::derive(&key_material)?;
+        let encrypted_secret = encrypt(secret, &key, iv_b64)?;
         crate::password::set_password(service, account, &encrypted_secret)?;
         Ok(encrypted_secret)
     }
 
+    /// Decrypts the secret with the key derived from `key_material`. Without key material, a key
+    /// cached in the session keyring by a previous call is used, see [key_cache].
     fn get_biometric_secret(
         service: &str,
         account: &str,
         key_material: Option<KeyMaterial>,
-    ) -> Result<String> {
+    ) -> Result<SecretBuf> {
+        let (key, cached) = match &key_material {
+            Some(key_material) => (EnvelopeKey::derive(key_material)?, false),
+            None => {
+                let key = key_cache::load(service, account)?.ok_or(anyhow!(
+                    "Key material is required for polkit protected keys"
+                ))?;
+                (EnvelopeKey::from_bytes(&key)?, true)
+            }
+        };
+
+        let secret = read_secret(service, account, &key, key_material.as_ref())?;
+
+        // Only cache freshly derived keys, so the cache timeout is not extended by cached unlocks
+        if !cached {
+            key_cache::store(service, account, &key.to_bytes())?;
+        }
+        Ok(secret)
+    }
+
+    fn has_biometric_secret(service: &str, account: &str) -> Result<bool> {
+        Ok(crate::password::find_password(service, account)?.is_some())
+    }
+
+    fn delete_biometric_secret(service: &str, account: &str) -> Result<()> {
+        key_cache::revoke(service, account)?;
+        crate::password::delete_password(service, account)
+    }
+
+    fn rotate_biometric_secret(
+        service: &str,
+        account: &str,
+        key_material: Option<KeyMaterial>,
+    ) -> Result<OsDerivedKey> {
         let key_material = key_material.ok_or(anyhow!(
             "Key material is required for polkit protected keys"
         ))?;
 
-        let encrypted_secret = crate::password::get_password(service, account)?;
-        let secret = CipherString::from_str(&encrypted_secret)?;
-        return Ok(decrypt(&secret, &key_material)?);
+        let derived = super::rotate::<Self>(service, account, key_material)?;
+        // The cached key can no longer decrypt the secret
+        key_cache::revoke(service, account)?;
+        Ok(derived)
     }
 }
 
+/// Ask polkit to authorize the unlock action, showing `message` in the authentication dialog.
+///
+/// Polkit only shows the message if we own the action, otherwise the prompt falls back to the
+/// message of the installed policy. Returns `false` if the user is not authorized or the prompt
+/// was cancelled.
+async fn check_authorization(
+    connection: &Connection,
+    message: &str,
+    cancellation_id: &str,
+) -> Result<bool> {
+    let proxy = AuthorityProxy::new(connection).await?;
+    let subject = Subject::new_for_owner(std::process::id(), None, None)?;
+
+    let mut details = HashMap::new();
+    details.insert("polkit.message", message);
+
+    let mut result = proxy
+        .check_authorization(
+            &subject,
+            super::polkit::POLICY_ACTION_ID,
+            &details,
+            CheckAuthorizationFlags::AllowUserInteraction.into(),
+            cancellation_id,
+        )
+        .await;
+
+    if matches!(&result, Err(zbus::Error::MethodError(_, Some(description), _))
+        if description.contains(UNTRUSTED_DETAILS_ERROR))
+    {
+        result = proxy
+            .check_authorization(
+                &subject,
+                super::polkit::POLICY_ACTION_ID,
+                &HashMap::new(),
+                CheckAuthorizationFlags::AllowUserInteraction.into(),
+                cancellation_id,
+            )
+            .await;
+    }
+
+    match result {
+        Ok(result) => Ok(result.is_authorized),
+        Err(zbus::Error::MethodError(name, _, _)) if name.as_str() == CANCELLED_ERROR => Ok(false),
+        Err(e) => Err(anyhow!("polkit authorization failed: {}", e)),
+    }
+}
+
+async fn cancel_check_authorization(connection: &Connection, cancellation_id: &str) -> Result<()> {
+    let proxy = AuthorityProxy::new(connection).await?;
+    proxy.cancel_check_authorization(cancellation_id).await?;
+    Ok(())
+}
+
+fn take_verification() -> bool {
+    VERIFIED_AT
+        .lock()
+        .unwrap()
+        .take()
+        .is_some_and(|at| at.elapsed() < VERIFICATION_TIMEOUT)
+}
+
 fn random_challenge() -> [u8; 16] {
     let mut challenge = [0u8; 16];
     rand::thread_rng().fill_bytes(&mut challenge);
     challenge
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use zbus::zvariant::OwnedValue;
+
+    use super::*;
+    use crate::biometric::BiometricTrait;
+
+    const AUTHORITY_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+
+    #[derive(zbus::DBusError, Debug)]
+    #[zbus(prefix = "org.freedesktop.PolicyKit1.Error")]
+    enum PolkitError {
+        #[zbus(error)]
+        ZBus(zbus::Error),
+        Failed(String),
+        Cancelled(String),
+    }
+
+    // (action id, details, cancellation id) of each CheckAuthorization call
+    type Requests = Arc<Mutex<Vec<(String, HashMap<String, String>, String)>>>;
+
+    #[derive(Default)]
+    struct MockAuthority {
+        authorized: bool,
+        fail: bool,
+        // Reject details like polkit does for callers that don't own the action
+        untrusted: bool,
+        // Wait for CancelCheckAuthorization before answering
+        wait_for_cancel: bool,
+        received: Arc<Notify>,
+        cancel: Arc<Notify>,
+        requests: Requests,
+        cancelled: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.PolicyKit1.Authority")]
+    impl MockAuthority {
+        async fn check_authorization(
+            &self,
+            _subject: (String, HashMap<String, OwnedValue>),
+            action_id: String,
+            details: HashMap<String, String>,
+            _flags: u32,
+            cancellation_id: String,
+        ) -> Result<(bool, bool, HashMap<String, String>), PolkitError> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((action_id, details.clone(), cancellation_id));
+            self.received.notify_one();
+
+            if self.untrusted && !details.is_empty() {
+                return Err(PolkitError::Failed(
+                    "Only trusted callers (e.g. uid 0 or an action owner) can use \
+                     CheckAuthorization() and pass details"
+                        .to_owned(),
+                ));
+            }
+            if self.wait_for_cancel {
+                self.cancel.notified().await;
+                return Err(PolkitError::Cancelled("Authorization cancelled".to_owned()));
+            }
+            if self.fail {
+                return Err(PolkitError::Failed("No authentication agent".to_owned()));
+            }
+            Ok((self.authorized, false, HashMap::new()))
+        }
+
+        async fn cancel_check_authorization(&self, cancellation_id: String) {
+            self.cancelled.lock().unwrap().push(cancellation_id);
+            self.cancel.notify_one();
+        }
+    }
+
+    async fn connect(authority: MockAuthority) -> (Connection, Connection) {
+        crate::dbus_mock::connect(|b| b.serve_at(AUTHORITY_PATH, authority)).await
+    }
+
+    #[tokio::test]
+    async fn check_authorization_passes_message() {
+        let authority = MockAuthority {
+            authorized: true,
+            ..Default::default()
+        };
+        let requests = authority.requests.clone();
+        let (client, _server) = connect(authority).await;
+
+        let result = check_authorization(&client, "Unlock your vault", "test-id").await;
+        assert!(result.unwrap());
+
+        let requests = requests.lock().unwrap();
+        let (action_id, details, cancellation_id) = &requests[0];
+        assert_eq!(action_id, super::super::polkit::POLICY_ACTION_ID);
+        assert_eq!(details["polkit.message"], "Unlock your vault");
+        assert_eq!(cancellation_id, "test-id");
+    }
+
+    #[tokio::test]
+    async fn check_authorization_retries_without_details_for_other_owners() {
+        let authority = MockAuthority {
+            authorized: true,
+            untrusted: true,
+            ..Default::default()
+        };
+        let requests = authority.requests.clone();
+        let (client, _server) = connect(authority).await;
+
+        let result = check_authorization(&client, "Unlock your vault", "test-id").await;
+        assert!(result.unwrap());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1["polkit.message"], "Unlock your vault");
+        assert!(requests[1].1.is_empty());
+        assert_eq!(requests[1].2, "test-id");
+    }
+
+    #[tokio::test]
+    async fn check_authorization_denied() {
+        let (client, _server) = connect(MockAuthority::default()).await;
+
+        let result = check_authorization(&client, "", "test-id").await;
+        assert!(!result.unwrap());
+    }
+
+    #[tokio::test]
+    async fn check_authorization_returns_errors() {
+        let authority = MockAuthority {
+            fail: true,
+            ..Default::default()
+        };
+        let (client, _server) = connect(authority).await;
+
+        let result = check_authorization(&client, "", "test-id").await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("No authentication agent"));
+    }
+
+    #[tokio::test]
+    async fn cancel_check_authorization_resolves_prompt() {
+        let authority = MockAuthority {
+            wait_for_cancel: true,
+            ..Default::default()
+        };
+        let received = authority.received.clone();
+        let cancelled = authority.cancelled.clone();
+        let (client, _server) = connect(authority).await;
+
+        let prompt = {
+            let client = client.clone();
+            tokio::spawn(async move { check_authorization(&client, "", "test-id").await })
+        };
+        received.notified().await;
+
+        cancel_check_authorization(&client, "test-id").await.unwrap();
+        assert!(!prompt.await.unwrap().unwrap());
+        assert_eq!(*cancelled.lock().unwrap(), vec!["test-id".to_owned()]);
+    }
+
+    #[test]
+    fn verification_is_consumed_once() {
+        *VERIFIED_AT.lock().unwrap() = Some(Instant::now());
+        assert!(take_verification());
+        assert!(!take_verification());
+
+        *VERIFIED_AT.lock().unwrap() = Instant::now().checked_sub(VERIFICATION_TIMEOUT);
+        assert!(!take_verification());
+    }
+
+    #[test]
+    fn finished_prompt_keeps_newer_pending_prompt() {
+        let older = PendingPromptGuard::set(PendingPrompt::Polkit("older".to_owned()));
+        let newer = PendingPromptGuard::set(PendingPrompt::Polkit("newer".to_owned()));
+
+        drop(older);
+        assert!(matches!(
+            PENDING_PROMPT.lock().unwrap().as_ref(),
+            Some((_, PendingPrompt::Polkit(id))) if id == "newer"
+        ));
+
+        drop(newer);
+        assert!(PENDING_PROMPT.lock().unwrap().is_none());
+    }
+
+    fn key_material(os_key_part_b64: &str) -> KeyMaterial {
+        KeyMaterial {
+            os_key_part_b64: os_key_part_b64.to_owned(),
+            client_key_part_b64: Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned()),
+        }
+    }
+
+    #[test]
+    fn has_and_delete_biometric_secret() {
+        scopeguard::defer! {
+            crate::password::delete_password("BitwardenTest", "HasDelete").unwrap_or(());
+        }
+        let os_key_part_b64 = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        <Biometric as BiometricTrait>::set_biometric_secret(
+            "BitwardenTest",
+            "HasDelete",
+            "secret",
+            Some(key_material(os_key_part_b64)),
+            "l9fhDUP/wDJcKwmEzcb/3w==",
+        )
+        .unwrap();
+        let result = <Biometric as BiometricTrait>::has_biometric_secret("BitwardenTest", "HasDelete");
+        assert!(result.unwrap());
+
+        <Biometric as BiometricTrait>::delete_biometric_secret("BitwardenTest", "HasDelete")
+            .unwrap();
+        let result = <Biometric as BiometricTrait>::has_biometric_secret("BitwardenTest", "HasDelete");
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn rotate_biometric_secret_reencrypts() {
+        scopeguard::defer! {
+            crate::password::delete_password("BitwardenTest", "Rotate").unwrap_or(());
+        }
+        let os_key_part_b64 = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        <Biometric as BiometricTrait>::set_biometric_secret(
+            "BitwardenTest",
+            "Rotate",
+            "secret",
+            Some(key_material(os_key_part_b64)),
+            "l9fhDUP/wDJcKwmEzcb/3w==",
+        )
+        .unwrap();
+
+        let derived = <Biometric as BiometricTrait>::rotate_biometric_secret(
+            "BitwardenTest",
+            "Rotate",
+            Some(key_material(os_key_part_b64)),
+        )
+        .unwrap();
+        assert_ne!(derived.key_b64, os_key_part_b64);
+
+        let result = <Biometric as BiometricTrait>::get_biometric_secret(
+            "BitwardenTest",
+            "Rotate",
+            Some(key_material(&derived.key_b64)),
+        );
+        assert_eq!(result.unwrap(), "secret");
+
+        let result = <Biometric as BiometricTrait>::get_biometric_secret(
+            "BitwardenTest",
+            "Rotate",
+            Some(key_material(os_key_part_b64)),
+        );
+        assert!(result.is_err());
+    }
+
+    fn assert_upgraded(account: &str, stored: &str) {
+        scopeguard::defer! {
+            crate::password::delete_password("BitwardenTest", account).unwrap_or(());
+        }
+        let os_key_part_b64 = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        crate::password::set_password("BitwardenTest", account, stored).unwrap();
+
+        let result = <Biometric as BiometricTrait>::get_biometric_secret(
+            "BitwardenTest",
+            account,
+            Some(key_material(os_key_part_b64)),
+        );
+        assert_eq!(result.unwrap(), "secret");
+
+        let upgraded = crate::password::get_password("BitwardenTest", account).unwrap();
+        assert!(upgraded.as_str().unwrap().starts_with("v2.hkdf-sha256.2."));
+        let result = <Biometric as BiometricTrait>::get_biometric_secret(
+            "BitwardenTest",
+            account,
+            Some(key_material(os_key_part_b64)),
+        );
+        assert_eq!(result.unwrap(), "secret");
+    }
+
+    #[test]
+    fn get_biometric_secret_upgrades_plain_secret() {
+        assert_upgraded("UpgradePlain", "secret");
+    }
+
+    #[test]
+    fn get_biometric_secret_upgrades_type_0_secret() {
+        // Encrypted with the legacy Sha256 key derivation
+        assert_upgraded(
+            "UpgradeType0",
+            "0.l9fhDUP/wDJcKwmEzcb/3w==|uP4LcqoCCj5FxBDP77NV6Q==",
+        );
+    }
+
+    #[test]
+    fn get_biometric_secret_keeps_malformed_secret() {
+        scopeguard::defer! {
+            crate::password::delete_password("BitwardenTest", "Malformed").unwrap_or(());
+        }
+        let stored = "0.l9fhDUP/wDJcKwmEzcb/3w==";
+        crate::password::set_password("BitwardenTest", "Malformed", stored).unwrap();
+
+        let result = <Biometric as BiometricTrait>::get_biometric_secret(
+            "BitwardenTest",
+            "Malformed",
+            Some(key_material("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=")),
+        );
+        assert!(result.is_err());
+
+        let unchanged = crate::password::get_password("BitwardenTest", "Malformed").unwrap();
+        assert_eq!(unchanged, stored);
+    }
+
+    #[test]
+    fn rotate_biometric_secret_requires_key() {
+        let result = <Biometric as BiometricTrait>::rotate_biometric_secret("", "", None);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Key material is required for polkit protected keys"
+        );
+    }
+}
@@ -1,5 +1,3 @@
-use std::str::FromStr;
-
 use anyhow::{anyhow, Result};
 use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
 use rand::RngCore;
@@ -27,12 +25,10 @@ use windows::{
     },
 };
 
-use crate::{
-    biometric::{KeyMaterial, OsDerivedKey},
-    crypto::CipherString,
-};
+use crate::biometric::{KeyMaterial, OsDerivedKey};
+use crate::secret::SecretBuf;
 
-use super::{decrypt, encrypt};
+use super::{encrypt, envelope::EnvelopeKey, read_secret};
 
 /// The Windows OS implementation of the biometric trait.
 pub struct Biometric {}
@@ -57,6 +53,10 @@ impl super::BiometricTrait for Biometric {
         }
     }
 
+    async fn cancel_prompt() -> Result<()> {
+        Err(anyhow!("Cancelling the Windows Hello prompt is not supported"))
+    }
+
     async fn available() -> Result<bool> {
         let ucv_available = UserConsentVerifier::CheckAvailabilityAsync()?.get()?;
 
@@ -130,7 +130,8 @@ impl super::BiometricTrait for Biometric {
             "Key material is required for Windows Hello protected keys"
         ))?;
 
-        let encrypted_secret = encrypt(secret, &key_material, iv_b64)?;
+        let key = EnvelopeKey::derive(&key_material)?;
+        let encrypted_secret = encrypt(secret, &key, iv_b64)?;
         crate::password::set_password(service, account, &encrypted_secret)?;
         Ok(encrypted_secret)
     }
@@ -139,24 +140,34 @@ impl super::BiometricTrait for Biometric {
         service: &str,
         account: &str,
         key_material: Option<KeyMaterial>,
-    ) -> Result<String> {
+    ) -> Result<SecretBuf> {
         let key_material = key_material.ok_or(anyhow!(
             "Key material is required for Windows Hello protected keys"
         ))?;
 
-        let encrypted_secret = crate::password::get_password(service, account)?;
-        match CipherString::from_str(&encrypted_secret) {
-            Ok(secret) => {
-                // If the secret is a CipherString, it is encrypted and we need to decrypt it.
-                let secret = decrypt(&secret, &key_material)?;
-                return Ok(secret);
-            }
-            Err(_) => {
-                // If the secret is not a CipherString, it is not encrypted and we can return it
-                //  directly.
-                return Ok(encrypted_secret);
-            }
-        }
+        // Unencrypted and type 0 secrets of older clients are upgraded to the current envelope
+        let key = EnvelopeKey::derive(&key_material)?;
+        read_secret(service, account, &key, Some(&key_material))
+    }
+
+    fn has_biometric_secret(service: &str, account: &str) -> Result<bool> {
+        Ok(crate::password::find_password(service, account)?.is_some())
+    }
+
+    fn delete_biometric_secret(service: &str, account: &str) -> Result<()> {
+        crate::password::delete_password(service, account)
+    }
+
+    fn rotate_biometric_secret(
+        service: &str,
+        account: &str,
+        key_material: Option<KeyMaterial>,
+    ) -> Result<OsDerivedKey> {
+        let key_material = key_material.ok_or(anyhow!(
+            "Key material is required for Windows Hello protected keys"
+        ))?;
+
+        super::rotate::<Self>(service, account, key_material)
     }
 }
 
@@ -220,9 +231,12 @@ fn set_focus(window: HWND) {
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use super::*;
 
-    use crate::biometric::{encrypt, BiometricTrait};
+    use crate::biometric::{encrypt, envelope::StoredSecret, BiometricTrait};
+    use crate::crypto::CipherString;
 
     #[test]
     #[cfg(feature = "manual_test")]
@@ -267,13 +281,12 @@ mod tests {
             client_key_part_b64: Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned()),
         };
         let iv_b64 = "l9fhDUP/wDJcKwmEzcb/3w==".to_owned();
-        let secret = encrypt("secret", &key_material, &iv_b64)
-            .unwrap()
-            .parse::<CipherString>()
-            .unwrap();
+        let key = EnvelopeKey::derive(&key_material).unwrap();
+        let secret = encrypt("secret", &key, &iv_b64).unwrap();
+        let secret = secret.strip_prefix("v2.hkdf-sha256.").unwrap();
 
-        match secret {
-            CipherString::AesCbc256_B64 { iv, data: _ } => {
+        match secret.parse::<CipherString>().unwrap() {
+            CipherString::AesCbc256_HmacSha256_B64 { iv, .. } => {
                 assert_eq!(iv_b64, base64_engine.encode(&iv));
             }
             _ => panic!("Invalid cipher string"),
@@ -288,7 +301,9 @@ mod tests {
             os_key_part_b64: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned(),
             client_key_part_b64: Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned()),
         };
-        assert_eq!(decrypt(&secret, &key_material).unwrap(), "secret")
+        let key = EnvelopeKey::derive(&key_material).unwrap();
+        let stored: StoredSecret = secret.to_string().parse().unwrap();
+        assert_eq!(stored.decrypt(&key, Some(&key_material)).unwrap(), "secret")
     }
 
     #[test]
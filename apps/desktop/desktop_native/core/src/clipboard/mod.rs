@@ -0,0 +1,717 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use arboard::Set;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+#[cfg(target_os = "linux")]
+mod paste_once;
+mod service;
+mod watch;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use watch::{watch, ClipboardChange, ClipboardSubscription};
+
+#[cfg(target_os = "linux")]
+pub use paste_once::{cancel_paste_once, write_paste_once, PasteOnceResult};
+
+static NEXT_CLEAR_ID: AtomicU32 = AtomicU32::new(1);
+static CLEAR_TASKS: Mutex<Option<ClearTasks>> = Mutex::new(None);
+static HASH_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// Finished clears are kept for status lookups, up to this many.
+const MAX_FINISHED_CLEARS: usize = 32;
+
+/// The selections to use. `Primary` is the selection pasted with middle-click on Linux, other
+/// platforms only have the clipboard, which `Both` falls back to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Target {
+    #[default]
+    Clipboard,
+    Primary,
+    Both,
+}
+
+impl Target {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Target::Clipboard => "clipboard",
+            Target::Primary => "primary",
+            Target::Both => "both",
+        }
+    }
+
+    fn selections(&self) -> &'static [Selection] {
+        match self {
+            Target::Clipboard => &[Selection::Clipboard],
+            Target::Primary => &[Selection::Primary],
+            #[cfg(target_os = "linux")]
+            Target::Both => &[Selection::Clipboard, Selection::Primary],
+            #[cfg(not(target_os = "linux"))]
+            Target::Both => &[Selection::Clipboard],
+        }
+    }
+}
+
+impl std::str::FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "clipboard" => Ok(Target::Clipboard),
+            "primary" => Ok(Target::Primary),
+            "both" => Ok(Target::Both),
+            _ => Err(anyhow!("Unknown clipboard target: {}", s)),
+        }
+    }
+}
+
+/// A single selection of a [Target].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Selection {
+    Clipboard,
+    Primary,
+}
+
+/// Read the text of the `target`. With [Target::Both], the primary selection is only read if the
+/// clipboard has no text.
+pub async fn read(target: Target) -> Result<String> {
+    let mut result = Err(anyhow!("No clipboard selection to read"));
+    for selection in target.selections() {
+        result = service::reply(service::service().read(*selection)).await;
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Write `text` to the `target`. The content is served by the clipboard thread of this process,
+/// so it does not block until another application reads it.
+///
+/// With `clear_after`, each selection is cleared once the duration has passed, unless it was
+/// replaced in the meantime. If writing a later selection fails, the clear is still scheduled for
+/// the selections already written before the error is returned.
+pub async fn write(
+    text: &str,
+    password: bool,
+    clear_after: Option<Duration>,
+    target: Target,
+) -> Result<Option<ClearHandle>> {
+    let schedule_clear = |selections: &'static [Selection]| {
+        clear_after.map(|clear_after| {
+            ClearHandle::spawn(
+                content_hash(text),
+                clear_after,
+                selections,
+                |selection| service::blocking_reply(service::service().read(selection)),
+                |selection| service::blocking_reply(service::service().clear(selection)),
+            )
+        })
+    };
+
+    let selections = target.selections();
+    for (written, selection) in selections.iter().enumerate() {
+        if let Err(e) = service::reply(service::service().write(text, password, *selection)).await {
+            if written > 0 {
+                schedule_clear(&selections[..written]);
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(schedule_clear(selections))
+}
+
+pub async fn clear(target: Target) -> Result<()> {
+    for selection in target.selections() {
+        service::reply(service::service().clear(*selection)).await?;
+    }
+    Ok(())
+}
+
+/// Look up a pending or recently finished clear by [ClearHandle::id].
+pub fn clear_handle(id: u32) -> Option<ClearHandle> {
+    CLEAR_TASKS
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .handles
+        .get(&id)
+        .cloned()
+}
+
+/// Keyed hash of selection content, so a stored hash cannot be used to guess short secrets
+/// offline. The key is random per process.
+fn content_hash(text: impl AsRef<[u8]>) -> [u8; 32] {
+    let key = HASH_KEY.get_or_init(rand::random);
+    let mut hmac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    hmac.update(text.as_ref());
+    hmac.finalize().into_bytes().into()
+}
+
+#[derive(Default)]
+struct ClearTasks {
+    handles: HashMap<u32, ClearHandle>,
+    /// Ids of finished clears, oldest first.
+    finished: VecDeque<u32>,
+}
+
+impl ClearTasks {
+    fn finish(&mut self, id: u32) {
+        self.finished.push_back(id);
+        while self.finished.len() > MAX_FINISHED_CLEARS {
+            if let Some(id) = self.finished.pop_front() {
+                self.handles.remove(&id);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearStatus {
+    Pending,
+    Cleared,
+    /// No selection contained the written text anymore, so they were left untouched.
+    Replaced,
+    Cancelled,
+    Failed,
+}
+
+impl ClearStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClearStatus::Pending => "pending",
+            ClearStatus::Cleared => "cleared",
+            ClearStatus::Replaced => "replaced",
+            ClearStatus::Cancelled => "cancelled",
+            ClearStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A clear scheduled by [write]. The clear runs on its own thread, independent of any window.
+#[derive(Clone)]
+pub struct ClearHandle {
+    id: u32,
+    state: Arc<(Mutex<ClearStatus>, Condvar)>,
+}
+
+impl ClearHandle {
+    // Only the hash of the text is kept, so the secret does not stay in memory until the clear
+    fn spawn<R, C>(
+        hash: [u8; 32],
+        clear_after: Duration,
+        selections: &'static [Selection],
+        mut read: R,
+        mut clear: C,
+    ) -> Self
+    where
+        R: FnMut(Selection) -> Result<String> + Send + 'static,
+        C: FnMut(Selection) -> Result<()> + Send + 'static,
+    {
+        let handle = ClearHandle {
+            id: NEXT_CLEAR_ID.fetch_add(1, Ordering::Relaxed),
+            state: Arc::new((Mutex::new(ClearStatus::Pending), Condvar::new())),
+        };
+        CLEAR_TASKS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(ClearTasks::default)
+            .handles
+            .insert(handle.id, handle.clone());
+
+        let (id, state) = (handle.id, handle.state.clone());
+        std::thread::spawn(move || {
+            let deadline = Instant::now() + clear_after;
+            let (status, cancelled) = &*state;
+
+            let mut status = status.lock().unwrap();
+            // Loop to ignore spurious wakeups
+            while *status == ClearStatus::Pending {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                status = cancelled.wait_timeout(status, deadline - now).unwrap().0;
+            }
+
+            if *status == ClearStatus::Pending {
+                // Each selection is checked on its own, the user may have replaced only one of them
+                let mut result = ClearStatus::Replaced;
+                for selection in selections {
+                    match read(*selection) {
+                        Ok(current) if content_hash(&current) == hash => match clear(*selection) {
+                            Ok(()) if result == ClearStatus::Replaced => {
+                                result = ClearStatus::Cleared
+                            }
+                            Ok(()) => {}
+                            Err(_) => result = ClearStatus::Failed,
+                        },
+                        // An empty or non-text selection was replaced as well
+                        _ => {}
+                    }
+                }
+                *status = result;
+            }
+            drop(status);
+
+            if let Some(tasks) = CLEAR_TASKS.lock().unwrap().as_mut() {
+                tasks.finish(id);
+            }
+        });
+
+        handle
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn status(&self) -> ClearStatus {
+        *self.state.0.lock().unwrap()
+    }
+
+    /// Cancel the clear. Returns `false` if it already ran.
+    pub fn cancel(&self) -> bool {
+        let (status, cancelled) = &*self.state;
+        let mut status = status.lock().unwrap();
+        if *status != ClearStatus::Pending {
+            return false;
+        }
+        *status = ClearStatus::Cancelled;
+        cancelled.notify_all();
+        true
+    }
+}
+
+// Exclude from windows clipboard history
+#[cfg(target_os = "windows")]
+fn clipboard_set(set: Set, password: bool) -> Set {
+    use arboard::SetExtWindows;
+
+    if password {
+        set.exclude_from_cloud().exclude_from_history()
+    } else {
+        set
+    }
+}
+
+// Passwords are offered with the `x-kde-passwordManagerHint: secret` target on X11 and Wayland,
+// which clipboard history managers such as Klipper, GPaste and cliphist use to skip the entry
+#[cfg(target_os = "linux")]
+fn clipboard_set(set: Set, password: bool) -> Set {
+    use arboard::SetExtLinux;
+
+    if password {
+        set.exclude_from_history()
+    } else {
+        set
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn clipboard_set(set: Set, _password: bool) -> Set {
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[cfg(any(feature = "manual_test", not(target_os = "linux")))]
+    async fn test_write_read() {
+        let message = "Hello world!";
+
+        write(message, false, None, Target::Clipboard)
+            .await
+            .unwrap();
+        assert_eq!(message, read(Target::Clipboard).await.unwrap());
+    }
+
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn write_blocking(text: &str, password: bool) {
+        service::blocking_reply(service::service().write(text, password, Selection::Clipboard))
+            .unwrap();
+    }
+
+    // Requires an X server without WAYLAND_DISPLAY set, e.g. `xvfb-run cargo test`
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_selection(target: &str) -> Vec<u8> {
+        x11_selection_of("CLIPBOARD", target)
+    }
+
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_selection_of(selection: &str, target: &str) -> Vec<u8> {
+        use x11rb::{
+            connection::Connection,
+            protocol::{xproto::*, Event},
+        };
+
+        let (conn, screen) = x11rb::connect(None).unwrap();
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            0,
+            window,
+            conn.setup().roots[screen].root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+        let atom = |name: &str| {
+            conn.intern_atom(false, name.as_bytes())
+                .unwrap()
+                .reply()
+                .unwrap()
+                .atom
+        };
+        let (selection, target, property) = (atom(selection), atom(target), atom("RESULT"));
+
+        conn.convert_selection(window, selection, target, property, x11rb::CURRENT_TIME)
+            .unwrap();
+        conn.flush().unwrap();
+        loop {
+            if let Event::SelectionNotify(event) = conn.wait_for_event().unwrap() {
+                assert_ne!(event.property, x11rb::NONE, "target was refused");
+                break;
+            }
+        }
+        conn.get_property(false, window, property, AtomEnum::ANY, 0, u32::MAX)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .value
+    }
+
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_targets() -> Vec<String> {
+        use x11rb::protocol::xproto::ConnectionExt as _;
+
+        let (conn, _) = x11rb::connect(None).unwrap();
+        x11_selection("TARGETS")
+            .chunks_exact(4)
+            .map(|atom| {
+                let atom = u32::from_ne_bytes(atom.try_into().unwrap());
+                let name = conn.get_atom_name(atom).unwrap().reply().unwrap().name;
+                String::from_utf8(name).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_password_offers_history_hint() {
+        write_blocking("secret", true);
+
+        assert!(x11_targets().contains(&"x-kde-passwordManagerHint".to_owned()));
+        assert_eq!(x11_selection("x-kde-passwordManagerHint"), b"secret");
+    }
+
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_text_offers_no_history_hint() {
+        write_blocking("not a secret", false);
+
+        assert!(!x11_targets().contains(&"x-kde-passwordManagerHint".to_owned()));
+    }
+
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_successive_writes_are_served_without_blocking() {
+        write_blocking("first", false);
+        write_blocking("second", false);
+
+        assert_eq!(x11_selection("UTF8_STRING"), b"second");
+        // Still served after a paste
+        assert_eq!(x11_selection("UTF8_STRING"), b"second");
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    async fn x11_password_in_both_selections_is_cleared() {
+        let handle = write(
+            "secret",
+            true,
+            Some(Duration::from_millis(500)),
+            Target::Both,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        for selection in ["CLIPBOARD", "PRIMARY"] {
+            assert_eq!(x11_selection_of(selection, "UTF8_STRING"), b"secret");
+            assert_eq!(
+                x11_selection_of(selection, "x-kde-passwordManagerHint"),
+                b"secret"
+            );
+        }
+
+        assert_eq!(wait_for(&handle), ClearStatus::Cleared);
+        assert!(read(Target::Primary).await.is_err());
+        assert!(read(Target::Clipboard).await.is_err());
+    }
+
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_clipboard_owner() -> u32 {
+        use x11rb::protocol::xproto::ConnectionExt as _;
+
+        let (conn, _) = x11rb::connect(None).unwrap();
+        let clipboard = conn
+            .intern_atom(false, b"CLIPBOARD")
+            .unwrap()
+            .reply()
+            .unwrap();
+        conn.get_selection_owner(clipboard.atom)
+            .unwrap()
+            .reply()
+            .unwrap()
+            .owner
+    }
+
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_paste_once_serves_one_paste() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        write_paste_once("secret", move |result| tx.send(result).unwrap()).unwrap();
+
+        // Neither listing the targets nor reading the history hint uses up the paste
+        assert!(x11_targets().contains(&"x-kde-passwordManagerHint".to_owned()));
+        assert_eq!(x11_selection("x-kde-passwordManagerHint"), b"secret");
+        assert!(rx.try_recv().is_err());
+
+        assert_eq!(x11_selection("UTF8_STRING"), b"secret");
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PasteOnceResult::Pasted
+        );
+        assert_eq!(x11_clipboard_owner(), x11rb::NONE);
+    }
+
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn x11_paste_once_cancel() {
+        let (first_tx, first_rx) = std::sync::mpsc::channel();
+        write_paste_once("first", move |result| first_tx.send(result).unwrap()).unwrap();
+        let (second_tx, second_rx) = std::sync::mpsc::channel();
+        write_paste_once("second", move |result| second_tx.send(result).unwrap()).unwrap();
+
+        assert_eq!(
+            first_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PasteOnceResult::Cancelled
+        );
+        assert_eq!(x11_selection("UTF8_STRING"), b"second");
+        assert_eq!(
+            second_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PasteOnceResult::Pasted
+        );
+        assert!(!cancel_paste_once());
+    }
+
+    // Requires a compositor with wlr-data-control, e.g. `sway --headless` or `cage`, with
+    // WAYLAND_DISPLAY pointing to it
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn wayland_password_offers_history_hint() {
+        use std::io::Read;
+        use wl_clipboard_rs::paste::{get_contents, get_mime_types, ClipboardType, MimeType, Seat};
+
+        write_blocking("secret", true);
+
+        let mime_types = get_mime_types(ClipboardType::Regular, Seat::Unspecified).unwrap();
+        assert!(mime_types.contains("x-kde-passwordManagerHint"));
+
+        let (mut pipe, _) = get_contents(
+            ClipboardType::Regular,
+            Seat::Unspecified,
+            MimeType::Specific("x-kde-passwordManagerHint"),
+        )
+        .unwrap();
+        let mut hint = Vec::new();
+        pipe.read_to_end(&mut hint).unwrap();
+        assert_eq!(hint, b"secret");
+    }
+
+    #[test]
+    #[cfg(all(feature = "manual_test", target_os = "linux"))]
+    fn wayland_paste_once_serves_one_paste() {
+        use std::io::Read;
+        use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType, Seat};
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        write_paste_once("secret", move |result| tx.send(result).unwrap()).unwrap();
+
+        let (mut pipe, _) = get_contents(
+            ClipboardType::Regular,
+            Seat::Unspecified,
+            MimeType::Specific("text/plain;charset=utf-8"),
+        )
+        .unwrap();
+        let mut text = Vec::new();
+        pipe.read_to_end(&mut text).unwrap();
+        assert_eq!(text, b"secret");
+
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            PasteOnceResult::Pasted
+        );
+        assert!(get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Text).is_err());
+    }
+
+    fn wait_for(handle: &ClearHandle) -> ClearStatus {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.status() == ClearStatus::Pending && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        handle.status()
+    }
+
+    fn spawn(
+        current: &'static str,
+        cleared: Arc<Mutex<bool>>,
+        clear_after: Duration,
+    ) -> ClearHandle {
+        ClearHandle::spawn(
+            content_hash("secret"),
+            clear_after,
+            &[Selection::Clipboard],
+            move |_| Ok(current.to_owned()),
+            move |_| {
+                *cleared.lock().unwrap() = true;
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn clears_unchanged_clipboard() {
+        let cleared = Arc::new(Mutex::new(false));
+        let handle = spawn("secret", cleared.clone(), Duration::from_millis(10));
+
+        assert_eq!(wait_for(&handle), ClearStatus::Cleared);
+        assert!(*cleared.lock().unwrap());
+        assert!(!handle.cancel());
+    }
+
+    #[test]
+    fn keeps_replaced_clipboard() {
+        let cleared = Arc::new(Mutex::new(false));
+        let handle = spawn("copied later", cleared.clone(), Duration::from_millis(10));
+
+        assert_eq!(wait_for(&handle), ClearStatus::Replaced);
+        assert!(!*cleared.lock().unwrap());
+    }
+
+    #[test]
+    fn clears_only_unchanged_selections() {
+        let cleared = Arc::new(Mutex::new(Vec::new()));
+        let handle = ClearHandle::spawn(
+            content_hash("secret"),
+            Duration::from_millis(10),
+            &[Selection::Clipboard, Selection::Primary],
+            |selection| match selection {
+                Selection::Clipboard => Ok("copied later".to_owned()),
+                Selection::Primary => Ok("secret".to_owned()),
+            },
+            {
+                let cleared = cleared.clone();
+                move |selection| {
+                    cleared.lock().unwrap().push(selection);
+                    Ok(())
+                }
+            },
+        );
+
+        assert_eq!(wait_for(&handle), ClearStatus::Cleared);
+        assert_eq!(*cleared.lock().unwrap(), vec![Selection::Primary]);
+    }
+
+    #[test]
+    fn parse_target() {
+        for target in [Target::Clipboard, Target::Primary, Target::Both] {
+            assert_eq!(target.as_str().parse::<Target>().unwrap(), target);
+        }
+        assert!("secondary".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn cancel_pending_clear() {
+        let cleared = Arc::new(Mutex::new(false));
+        let handle = spawn("secret", cleared.clone(), Duration::from_secs(60));
+
+        assert_eq!(handle.status(), ClearStatus::Pending);
+        assert!(handle.cancel());
+        assert_eq!(wait_for(&handle), ClearStatus::Cancelled);
+        assert!(!*cleared.lock().unwrap());
+    }
+
+    #[test]
+    fn finished_clears_are_capped() {
+        let handles: Vec<_> = (0..MAX_FINISHED_CLEARS + 1)
+            .map(|_| {
+                spawn(
+                    "secret",
+                    Arc::new(Mutex::new(false)),
+                    Duration::from_secs(60),
+                )
+            })
+            .collect();
+        for handle in &handles {
+            handle.cancel();
+        }
+
+        // The clear threads finish in any order, one of them is dropped once all have finished
+        let remaining = || {
+            handles
+                .iter()
+                .filter(|handle| clear_handle(handle.id()).is_some())
+                .count()
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while remaining() > MAX_FINISHED_CLEARS && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(remaining() <= MAX_FINISHED_CLEARS);
+    }
+
+    #[test]
+    fn content_hash_is_keyed() {
+        use sha2::Digest;
+
+        assert_eq!(content_hash("secret"), content_hash("secret"));
+        assert_ne!(content_hash("secret"), content_hash("secreT"));
+        assert_ne!(
+            content_hash("secret"),
+            <[u8; 32]>::from(Sha256::digest("secret"))
+        );
+    }
+
+    #[test]
+    fn lookup_handle_by_id() {
+        let cleared = Arc::new(Mutex::new(false));
+        let handle = spawn("secret", cleared, Duration::from_secs(60));
+
+        let found = clear_handle(handle.id()).unwrap();
+        assert!(found.cancel());
+        assert_eq!(handle.status(), ClearStatus::Cancelled);
+        assert!(clear_handle(0).is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
+
+use anyhow::Result;
+
+use super::{wayland, x11};
+
+/// Targets the text is offered as. `UTF8_STRING` is included on Wayland for XWayland clients.
+pub(super) const TEXT_TARGETS: [&str; 3] =
+    ["UTF8_STRING", "text/plain;charset=utf-8", "text/plain"];
+/// Clipboard history managers skip entries offering this target, so they don't use up the paste.
+pub(super) const HINT_TARGET: &str = "x-kde-passwordManagerHint";
+pub(super) const HINT_VALUE: &[u8] = b"secret";
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+static CURRENT: Mutex<Option<(u32, Canceller)>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasteOnceResult {
+    Pasted,
+    /// Another application took the selection before anything was pasted.
+    Replaced,
+    Cancelled,
+    Failed,
+}
+
+impl PasteOnceResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PasteOnceResult::Pasted => "pasted",
+            PasteOnceResult::Replaced => "replaced",
+            PasteOnceResult::Cancelled => "cancelled",
+            PasteOnceResult::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Request {
+    /// Counts as the one paste.
+    Paste,
+    Hint,
+    Unsupported,
+}
+
+pub(super) fn request(target: &str) -> Request {
+    if TEXT_TARGETS.contains(&target) {
+        Request::Paste
+    } else if target == HINT_TARGET {
+        Request::Hint
+    } else {
+        Request::Unsupported
+    }
+}
+
+/// Offer `text` on the clipboard until it has been pasted once, after which the selection is
+/// released and the clipboard is empty. `on_done` is called from a background thread once the
+/// paste happened, or the offer ended otherwise.
+///
+/// The selection is owned by this process directly, on Wayland through wlr-data-control and on
+/// X11 through a hidden window. A previous paste-once offer is cancelled.
+pub fn write_paste_once<F>(text: &str, on_done: F) -> Result<()>
+where
+    F: FnOnce(PasteOnceResult) + Send + 'static,
+{
+    cancel_paste_once();
+
+    let owner = Owner::own(text)?;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    *CURRENT.lock().unwrap() = Some((id, owner.canceller()));
+
+    std::thread::spawn(move || {
+        let result = owner.run().unwrap_or(PasteOnceResult::Failed);
+
+        let mut current = CURRENT.lock().unwrap();
+        if matches!(*current, Some((current_id, _)) if current_id == id) {
+            *current = None;
+        }
+        drop(current);
+
+        on_done(result);
+    });
+    Ok(())
+}
+
+/// Withdraw the pending paste-once offer. Returns `false` if there was none.
+pub fn cancel_paste_once() -> bool {
+    match CURRENT.lock().unwrap().take() {
+        Some((_, canceller)) => {
+            canceller.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+enum Owner {
+    X11(x11::Owner),
+    Wayland(wayland::Owner),
+}
+
+impl Owner {
+    fn own(text: &str) -> Result<Self> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Ok(Owner::Wayland(wayland::Owner::own(text)?))
+        } else {
+            Ok(Owner::X11(x11::Owner::own(text)?))
+        }
+    }
+
+    fn canceller(&self) -> Canceller {
+        match self {
+            Owner::X11(owner) => Canceller::X11(owner.canceller()),
+            Owner::Wayland(owner) => Canceller::Wayland(owner.canceller()),
+        }
+    }
+
+    fn run(self) -> Result<PasteOnceResult> {
+        match self {
+            Owner::X11(owner) => owner.run(),
+            Owner::Wayland(owner) => owner.run(),
+        }
+    }
+}
+
+enum Canceller {
+    X11(x11::Canceller),
+    Wayland(wayland::Canceller),
+}
+
+impl Canceller {
+    fn cancel(&self) {
+        match self {
+            Canceller::X11(canceller) => canceller.cancel(),
+            Canceller::Wayland(canceller) => canceller.cancel(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_text_targets_count_as_paste() {
+        assert_eq!(request("UTF8_STRING"), Request::Paste);
+        assert_eq!(request("text/plain;charset=utf-8"), Request::Paste);
+        assert_eq!(request("x-kde-passwordManagerHint"), Request::Hint);
+        assert_eq!(request("TARGETS"), Request::Unsupported);
+        assert_eq!(request("image/png"), Request::Unsupported);
+    }
+}
@@ -0,0 +1,317 @@
+use std::sync::{mpsc, OnceLock};
+
+use anyhow::{anyhow, Result};
+use arboard::Clipboard;
+use tokio::sync::oneshot;
+
+use super::Selection;
+
+static SERVICE: OnceLock<Service> = OnceLock::new();
+
+/// The process wide service, started on first use.
+pub(super) fn service() -> &'static Service {
+    SERVICE.get_or_init(Service::spawn::<Clipboard>)
+}
+
+/// Access to the system clipboard. Implemented by [arboard::Clipboard], and by fakes in tests.
+pub(super) trait Backend: Sized {
+    fn connect() -> Result<Self>;
+    fn get_text(&mut self, selection: Selection) -> Result<String>;
+    fn set_text(&mut self, text: &str, password: bool, selection: Selection) -> Result<()>;
+    fn clear(&mut self, selection: Selection) -> Result<()>;
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for Clipboard {
+    fn connect() -> Result<Self> {
+        Ok(Clipboard::new()?)
+    }
+
+    fn get_text(&mut self, selection: Selection) -> Result<String> {
+        use arboard::GetExtLinux;
+
+        Ok(self.get().clipboard(linux_kind(selection)).text()?)
+    }
+
+    fn set_text(&mut self, text: &str, password: bool, selection: Selection) -> Result<()> {
+        use arboard::SetExtLinux;
+
+        Ok(super::clipboard_set(self.set(), password)
+            .clipboard(linux_kind(selection))
+            .text(text)?)
+    }
+
+    fn clear(&mut self, selection: Selection) -> Result<()> {
+        use arboard::ClearExtLinux;
+
+        Ok(self.clear_with().clipboard(linux_kind(selection))?)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn linux_kind(selection: Selection) -> arboard::LinuxClipboardKind {
+    match selection {
+        Selection::Clipboard => arboard::LinuxClipboardKind::Clipboard,
+        Selection::Primary => arboard::LinuxClipboardKind::Primary,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Backend for Clipboard {
+    fn connect() -> Result<Self> {
+        Ok(Clipboard::new()?)
+    }
+
+    fn get_text(&mut self, selection: Selection) -> Result<String> {
+        only_clipboard(selection)?;
+        Ok(Clipboard::get_text(self)?)
+    }
+
+    fn set_text(&mut self, text: &str, password: bool, selection: Selection) -> Result<()> {
+        only_clipboard(selection)?;
+        Ok(super::clipboard_set(self.set(), password).text(text)?)
+    }
+
+    fn clear(&mut self, selection: Selection) -> Result<()> {
+        only_clipboard(selection)?;
+        Ok(Clipboard::clear(self)?)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn only_clipboard(selection: Selection) -> Result<()> {
+    match selection {
+        Selection::Clipboard => Ok(()),
+        Selection::Primary => Err(anyhow!("The primary selection is only supported on Linux")),
+    }
+}
+
+enum Command {
+    Read {
+        selection: Selection,
+        reply: oneshot::Sender<Result<String>>,
+    },
+    Write {
+        text: String,
+        password: bool,
+        selection: Selection,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    Clear {
+        selection: Selection,
+        reply: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// Owns the clipboard on a dedicated thread. On Linux the written content is served by this
+/// process, so the clipboard is kept alive for as long as the process runs instead of per call.
+pub(super) struct Service {
+    /// The error message if the thread could not be spawned, returned by every request.
+    commands: Result<mpsc::Sender<Command>, String>,
+}
+
+impl Service {
+    pub(super) fn spawn<B: Backend>() -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .name("clipboard".to_owned())
+            .spawn(move || run::<B>(receiver));
+
+        Service {
+            commands: spawned
+                .map(|_| commands)
+                .map_err(|e| format!("Failed to spawn the clipboard thread: {}", e)),
+        }
+    }
+
+    pub(super) fn read(&self, selection: Selection) -> oneshot::Receiver<Result<String>> {
+        self.send(|reply| Command::Read { selection, reply })
+    }
+
+    pub(super) fn write(
+        &self,
+        text: &str,
+        password: bool,
+        selection: Selection,
+    ) -> oneshot::Receiver<Result<()>> {
+        self.send(|reply| Command::Write {
+            text: text.to_owned(),
+            password,
+            selection,
+            reply,
+        })
+    }
+
+    pub(super) fn clear(&self, selection: Selection) -> oneshot::Receiver<Result<()>> {
+        self.send(|reply| Command::Clear { selection, reply })
+    }
+
+    fn send<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<T>>) -> Command,
+    ) -> oneshot::Receiver<Result<T>> {
+        let (reply, receiver) = oneshot::channel();
+        match &self.commands {
+            // A send only fails if the thread is gone, in which case the dropped reply reports it
+            Ok(commands) => {
+                let _ = commands.send(command(reply));
+            }
+            Err(e) => {
+                let _ = reply.send(Err(anyhow!("{}", e)));
+            }
+        }
+        receiver
+    }
+}
+
+/// Await a reply from the service.
+pub(super) async fn reply<T>(receiver: oneshot::Receiver<Result<T>>) -> Result<T> {
+    receiver
+        .await
+        .map_err(|_| anyhow!("The clipboard service stopped"))?
+}
+
+/// Wait for a reply from the service outside of an async context.
+pub(super) fn blocking_reply<T>(receiver: oneshot::Receiver<Result<T>>) -> Result<T> {
+    receiver
+        .blocking_recv()
+        .map_err(|_| anyhow!("The clipboard service stopped"))?
+}
+
+fn run<B: Backend>(commands: mpsc::Receiver<Command>) {
+    let mut backend: Option<B> = None;
+
+    for command in commands {
+        // Reconnect lazily, e.g. after the display connection was lost
+        let clipboard = match backend.as_mut() {
+            Some(clipboard) => Ok(clipboard),
+            None => B::connect().map(|clipboard| backend.insert(clipboard)),
+        };
+
+        let failed = match command {
+            // Reading an empty or non-text clipboard fails as well, so reads never reconnect
+            Command::Read { selection, reply } => {
+                let _ = reply.send(clipboard.and_then(|c| c.get_text(selection)));
+                false
+            }
+            Command::Write {
+                text,
+                password,
+                selection,
+                reply,
+            } => {
+                let result = clipboard.and_then(|c| c.set_text(&text, password, selection));
+                let failed = result.is_err();
+                let _ = reply.send(result);
+                failed
+            }
+            Command::Clear { selection, reply } => {
+                let result = clipboard.and_then(|c| c.clear(selection));
+                let failed = result.is_err();
+                let _ = reply.send(result);
+                failed
+            }
+        };
+        if failed {
+            backend = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Mutex,
+        },
+    };
+
+    use anyhow::bail;
+
+    use super::*;
+
+    static CONTENT: Mutex<Option<HashMap<Selection, String>>> = Mutex::new(None);
+    static CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+
+    /// A clipboard shared by all connections, like the system clipboard. Writing `disconnect`
+    /// fails like a lost display connection.
+    struct FakeClipboard;
+
+    impl Backend for FakeClipboard {
+        fn connect() -> Result<Self> {
+            CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+            Ok(FakeClipboard)
+        }
+
+        fn get_text(&mut self, selection: Selection) -> Result<String> {
+            CONTENT
+                .lock()
+                .unwrap()
+                .get_or_insert_with(HashMap::new)
+                .get(&selection)
+                .cloned()
+                .ok_or(anyhow!("The clipboard is empty"))
+        }
+
+        fn set_text(&mut self, text: &str, _password: bool, selection: Selection) -> Result<()> {
+            if text == "disconnect" {
+                bail!("Connection lost");
+            }
+            CONTENT
+                .lock()
+                .unwrap()
+                .get_or_insert_with(HashMap::new)
+                .insert(selection, text.to_owned());
+            Ok(())
+        }
+
+        fn clear(&mut self, selection: Selection) -> Result<()> {
+            CONTENT
+                .lock()
+                .unwrap()
+                .get_or_insert_with(HashMap::new)
+                .remove(&selection);
+            Ok(())
+        }
+    }
+
+    // The fake clipboard is global, so everything runs in one test
+    #[test]
+    fn successive_writes_clears_and_reconnects() {
+        let service = Service::spawn::<FakeClipboard>();
+
+        let (clipboard, primary) = (Selection::Clipboard, Selection::Primary);
+
+        blocking_reply(service.write("first", false, clipboard)).unwrap();
+        blocking_reply(service.write("second", true, clipboard)).unwrap();
+        blocking_reply(service.write("username", false, primary)).unwrap();
+        assert_eq!(blocking_reply(service.read(clipboard)).unwrap(), "second");
+        assert_eq!(blocking_reply(service.read(primary)).unwrap(), "username");
+
+        blocking_reply(service.clear(clipboard)).unwrap();
+        assert!(blocking_reply(service.read(clipboard)).is_err());
+        assert_eq!(blocking_reply(service.read(primary)).unwrap(), "username");
+        assert_eq!(CONNECTIONS.load(Ordering::SeqCst), 1);
+
+        assert!(blocking_reply(service.write("disconnect", false, clipboard)).is_err());
+        blocking_reply(service.write("third", false, clipboard)).unwrap();
+        assert_eq!(blocking_reply(service.read(clipboard)).unwrap(), "third");
+        assert_eq!(CONNECTIONS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn spawn_failure_is_returned_by_requests() {
+        let service = Service {
+            commands: Err("Failed to spawn the clipboard thread".to_owned()),
+        };
+
+        let result = blocking_reply(service.read(Selection::Clipboard));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Failed to spawn the clipboard thread"
+        );
+        assert!(blocking_reply(service.write("text", false, Selection::Clipboard)).is_err());
+    }
+}
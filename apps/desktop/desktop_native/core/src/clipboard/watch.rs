@@ -0,0 +1,220 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+
+use super::{service, Selection, Target};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClipboardChange {
+    /// The selection that changed, [Target::Clipboard] or [Target::Primary].
+    pub target: Target,
+    /// The new text, if requested and the selection contains text.
+    pub text: Option<String>,
+}
+
+/// Send a [ClipboardChange] to `tx` whenever a selection of `target` changes, including changes
+/// made by this process. With `content`, the new text is read and included.
+///
+/// The text of a paste-once offer is never read, as that would use up its paste. On Wayland this
+/// applies to every selection offering the password manager hint, as the owner is unknown there.
+///
+/// Changes are sent until [ClipboardSubscription::stop] is called or the receiver is dropped.
+pub async fn watch(
+    tx: tokio::sync::mpsc::Sender<ClipboardChange>,
+    target: Target,
+    content: bool,
+) -> Result<ClipboardSubscription> {
+    let watcher = Watcher::connect(target.selections())?;
+    let subscription = ClipboardSubscription {
+        stopped: Arc::new(AtomicBool::new(false)),
+    };
+
+    let stopped = subscription.stopped.clone();
+    std::thread::spawn(move || {
+        let _ = watcher.run(|selection, paste_once| {
+            if stopped.load(Ordering::SeqCst) {
+                return false;
+            }
+            let text = if content && !paste_once {
+                service::blocking_reply(service::service().read(selection)).ok()
+            } else {
+                None
+            };
+            tx.blocking_send(ClipboardChange {
+                target: selection.into(),
+                text,
+            })
+            .is_ok()
+        });
+        stopped.store(true, Ordering::SeqCst);
+    });
+    Ok(subscription)
+}
+
+/// Returned by [watch]. Dropping the subscription does not stop it.
+#[derive(Debug)]
+pub struct ClipboardSubscription {
+    stopped: Arc<AtomicBool>,
+}
+
+impl ClipboardSubscription {
+    /// Stop sending changes. The watcher thread exits with the next selection change, as the
+    /// display connection offers no way to interrupt the wait.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+impl From<Selection> for Target {
+    fn from(selection: Selection) -> Self {
+        match selection {
+            Selection::Clipboard => Target::Clipboard,
+            Selection::Primary => Target::Primary,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+enum Watcher {
+    X11(Box<super::x11::Watcher>),
+    Wayland(super::wayland::Watcher),
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn connect(selections: &[Selection]) -> Result<Self> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Ok(Watcher::Wayland(super::wayland::Watcher::connect(
+                selections,
+            )?))
+        } else {
+            Ok(Watcher::X11(Box::new(super::x11::Watcher::connect(
+                selections,
+            )?)))
+        }
+    }
+
+    fn run(self, on_change: impl FnMut(Selection, bool) -> bool) -> Result<()> {
+        match self {
+            Watcher::X11(watcher) => watcher.run(on_change),
+            Watcher::Wayland(watcher) => watcher.run(on_change),
+        }
+    }
+}
+
+/// Polls the clipboard, there is no change notification without a window on other platforms.
+#[cfg(not(target_os = "linux"))]
+struct Watcher {
+    selections: &'static [Selection],
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    const INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    fn connect(selections: &'static [Selection]) -> Result<Self> {
+        Ok(Watcher { selections })
+    }
+
+    /// There is no paste-once mode on other platforms, so changes are never reported as one.
+    fn run(self, mut on_change: impl FnMut(Selection, bool) -> bool) -> Result<()> {
+        use super::content_hash;
+
+        // Only hashes are kept, so copied secrets do not stay in memory
+        let hash = |selection| {
+            service::blocking_reply(service::service().read(selection))
+                .ok()
+                .map(content_hash)
+        };
+        let mut hashes: Vec<_> = self.selections.iter().map(|s| hash(*s)).collect();
+
+        loop {
+            std::thread::sleep(Self::INTERVAL);
+            for (selection, last) in self.selections.iter().zip(hashes.iter_mut()) {
+                let current = hash(*selection);
+                if current != *last {
+                    *last = current;
+                    if !on_change(*selection, false) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "manual_test"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_changes_with_content() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let subscription = watch(tx, Target::Clipboard, true).await.unwrap();
+
+        super::super::write("otpauth://totp/test", false, None, Target::Clipboard)
+            .await
+            .unwrap();
+
+        let change = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            change,
+            ClipboardChange {
+                target: Target::Clipboard,
+                text: Some("otpauth://totp/test".to_owned()),
+            }
+        );
+
+        // The change after stopping ends the watcher without being sent
+        subscription.stop();
+        super::super::write("after stop", false, None, Target::Clipboard)
+            .await
+            .unwrap();
+        let after_stop = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(after_stop.unwrap(), None);
+        assert!(subscription.is_stopped());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn does_not_read_paste_once_offers() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let subscription = watch(tx, Target::Clipboard, true).await.unwrap();
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        super::super::write_paste_once("secret", move |result| done_tx.send(result).unwrap())
+            .unwrap();
+
+        let change = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            change,
+            ClipboardChange {
+                target: Target::Clipboard,
+                text: None,
+            }
+        );
+
+        // The offer is still pending, so it ends by the cancellation rather than a paste
+        assert!(super::super::cancel_paste_once());
+        assert_eq!(
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            super::super::PasteOnceResult::Cancelled
+        );
+        subscription.stop();
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::Write,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use wayland_client::{
+    backend::ObjectId,
+    delegate_noop, event_created_child,
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{wl_registry::WlRegistry, wl_seat::WlSeat},
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+use super::paste_once::{request, PasteOnceResult, Request, HINT_TARGET, HINT_VALUE, TEXT_TARGETS};
+use super::Selection;
+
+/// Owns the Wayland selection of the first seat through wlr-data-control until the first paste.
+pub(super) struct Owner {
+    queue: EventQueue<State>,
+    state: State,
+    device: ZwlrDataControlDeviceV1,
+    source: ZwlrDataControlSourceV1,
+    /// An eventfd signalled by [Canceller].
+    cancel: Arc<OwnedFd>,
+}
+
+struct State {
+    text: String,
+    result: Option<PasteOnceResult>,
+}
+
+impl Owner {
+    pub(super) fn own(text: &str) -> Result<Self> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<State>(&conn)?;
+        let qh = queue.handle();
+
+        let seat: WlSeat = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|_| anyhow!("No Wayland seat found"))?;
+        let manager: ZwlrDataControlManagerV1 = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|_| anyhow!("The compositor does not support wlr-data-control"))?;
+
+        let source = manager.create_data_source(&qh, ());
+        for mime_type in TEXT_TARGETS.into_iter().chain([HINT_TARGET]) {
+            source.offer(mime_type.to_owned());
+        }
+        let device = manager.get_data_device(&seat, &qh, ());
+        device.set_selection(Some(&source));
+
+        let mut state = State {
+            text: text.to_owned(),
+            result: None,
+        };
+        queue.roundtrip(&mut state)?;
+        if state.result.is_some() {
+            bail!("Failed to take ownership of the clipboard");
+        }
+
+        let cancel = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if cancel < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Owner {
+            queue,
+            state,
+            device,
+            source,
+            cancel: Arc::new(unsafe { OwnedFd::from_raw_fd(cancel) }),
+        })
+    }
+
+    pub(super) fn canceller(&self) -> Canceller {
+        Canceller {
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    pub(super) fn run(mut self) -> Result<PasteOnceResult> {
+        let result = loop {
+            self.queue.flush()?;
+            self.queue.dispatch_pending(&mut self.state)?;
+            if let Some(result) = self.state.result {
+                break result;
+            }
+
+            // Events may have been queued in the meantime
+            let Some(guard) = self.queue.prepare_read() else {
+                continue;
+            };
+            let mut fds = [
+                libc::pollfd {
+                    fd: guard.connection_fd().as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: self.cancel.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let error = std::io::Error::last_os_error();
+                if error.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error.into());
+            }
+
+            if fds[1].revents != 0 {
+                break PasteOnceResult::Cancelled;
+            }
+            if fds[0].revents != 0 {
+                guard.read()?;
+            }
+        };
+
+        // Destroying the source clears the selection, unless another client took it meanwhile
+        self.source.destroy();
+        self.device.destroy();
+        self.queue.roundtrip(&mut self.state)?;
+        Ok(result)
+    }
+}
+
+/// Wakes up [Owner::run] from another thread.
+pub(super) struct Canceller {
+    cancel: Arc<OwnedFd>,
+}
+
+impl Canceller {
+    pub(super) fn cancel(&self) {
+        unsafe { libc::eventfd_write(self.cancel.as_raw_fd(), 1) };
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _source: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // Requests queued behind the paste are not answered
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd }
+                if state.result.is_none() =>
+            {
+                let mut pipe = File::from(fd);
+                match request(&mime_type) {
+                    Request::Paste => {
+                        if pipe.write_all(state.text.as_bytes()).is_ok() {
+                            state.result = Some(PasteOnceResult::Pasted);
+                        }
+                    }
+                    Request::Hint => {
+                        let _ = pipe.write_all(HINT_VALUE);
+                    }
+                    Request::Unsupported => {}
+                }
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                state.result.get_or_insert(PasteOnceResult::Replaced);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _device: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::DataOffer { id } => id.destroy(),
+            zwlr_data_control_device_v1::Event::Finished => {
+                state.result.get_or_insert(PasteOnceResult::Failed);
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+delegate_noop!(State: ignore ZwlrDataControlOfferV1);
+delegate_noop!(State: ZwlrDataControlManagerV1);
+delegate_noop!(State: ignore WlSeat);
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _state: &mut Self,
+        _registry: &WlRegistry,
+        _event: <WlRegistry as Proxy>::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+/// Reports selection changes of the first seat through wlr-data-control.
+pub(super) struct Watcher {
+    queue: EventQueue<WatchState>,
+    state: WatchState,
+    selections: Vec<Selection>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    /// Changed selections, and whether the new selection offers [HINT_TARGET].
+    changes: Vec<(Selection, bool)>,
+    /// Pending offers that include [HINT_TARGET].
+    hinted: HashSet<ObjectId>,
+    finished: bool,
+}
+
+impl Watcher {
+    pub(super) fn connect(selections: &[Selection]) -> Result<Self> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<WatchState>(&conn)?;
+        let qh = queue.handle();
+
+        let seat: WlSeat = globals
+            .bind(&qh, 1..=1, ())
+            .map_err(|_| anyhow!("No Wayland seat found"))?;
+        // Primary selection events were added in version 2
+        let manager: ZwlrDataControlManagerV1 = globals
+            .bind(&qh, 1..=2, ())
+            .map_err(|_| anyhow!("The compositor does not support wlr-data-control"))?;
+        if selections.contains(&Selection::Primary) && manager.version() < 2 {
+            bail!("The compositor does not support watching the primary selection");
+        }
+        manager.get_data_device(&seat, &qh, ());
+
+        // The device reports the current selections first, which are not changes
+        let mut state = WatchState::default();
+        queue.roundtrip(&mut state)?;
+        state.changes.clear();
+
+        Ok(Watcher {
+            queue,
+            state,
+            selections: selections.to_vec(),
+        })
+    }
+
+    /// Call `on_change` for every change until it returns `false`. Its second argument tells
+    /// whether the selection offers [HINT_TARGET], which includes paste-once offers. The owner of
+    /// an offer is unknown on Wayland, so they cannot be told apart from other hinted selections.
+    pub(super) fn run(mut self, mut on_change: impl FnMut(Selection, bool) -> bool) -> Result<()> {
+        loop {
+            self.queue.blocking_dispatch(&mut self.state)?;
+            for (selection, hinted) in self.state.changes.drain(..) {
+                if self.selections.contains(&selection) && !on_change(selection, hinted) {
+                    return Ok(());
+                }
+            }
+            if self.state.finished {
+                bail!("The data control device was destroyed");
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for WatchState {
+    fn event(
+        state: &mut Self,
+        _device: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Offers announce their mime types before the selection event, so they are only destroyed
+        // once it arrived
+        match event {
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                let hinted = state.take_offer(id);
+                state.changes.push((Selection::Clipboard, hinted))
+            }
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                let hinted = state.take_offer(id);
+                state.changes.push((Selection::Primary, hinted))
+            }
+            zwlr_data_control_device_v1::Event::Finished => state.finished = true,
+            _ => {}
+        }
+    }
+
+    event_created_child!(WatchState, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl WatchState {
+    /// Destroy the offer of a new selection, returning whether it offered [HINT_TARGET].
+    fn take_offer(&mut self, offer: Option<ZwlrDataControlOfferV1>) -> bool {
+        let Some(offer) = offer else {
+            return false;
+        };
+        offer.destroy();
+        self.hinted.remove(&offer.id())
+    }
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for WatchState {
+    fn event(
+        state: &mut Self,
+        offer: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            if mime_type == HINT_TARGET {
+                state.hinted.insert(offer.id());
+            }
+        }
+    }
+}
+
+delegate_noop!(WatchState: ZwlrDataControlManagerV1);
+delegate_noop!(WatchState: ignore WlSeat);
+
+impl Dispatch<WlRegistry, GlobalListContents> for WatchState {
+    fn event(
+        _state: &mut Self,
+        _registry: &WlRegistry,
+        _event: <WlRegistry as Proxy>::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
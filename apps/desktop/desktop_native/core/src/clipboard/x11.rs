@@ -0,0 +1,274 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xfixes::{ConnectionExt as _, SelectionEventMask},
+        xproto::*,
+        Event,
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+    COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, CURRENT_TIME, NONE,
+};
+
+use super::paste_once::{request, PasteOnceResult, Request, HINT_TARGET, HINT_VALUE, TEXT_TARGETS};
+use super::Selection;
+
+// Windows of the paste-once owners in this process, so watchers can tell them apart
+static OWNER_WINDOWS: Mutex<Vec<Window>> = Mutex::new(Vec::new());
+
+/// Owns the X11 `CLIPBOARD` selection with a hidden window until the first paste.
+pub(super) struct Owner {
+    conn: Arc<RustConnection>,
+    window: Window,
+    clipboard: Atom,
+    targets: Atom,
+    cancel: Atom,
+    /// The offered targets with their names, [TEXT_TARGETS] followed by [HINT_TARGET].
+    offered: Vec<(Atom, &'static str)>,
+    text: String,
+}
+
+impl Owner {
+    pub(super) fn own(text: &str) -> Result<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let window = conn.generate_id()?;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            conn.setup().roots[screen].root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )?;
+
+        let mut offered = Vec::new();
+        for name in TEXT_TARGETS.into_iter().chain([HINT_TARGET]) {
+            offered.push((intern(&conn, name)?, name));
+        }
+        let owner = Owner {
+            clipboard: intern(&conn, "CLIPBOARD")?,
+            targets: intern(&conn, "TARGETS")?,
+            cancel: intern(&conn, "_BITWARDEN_PASTE_ONCE_CANCEL")?,
+            conn: Arc::new(conn),
+            window,
+            offered,
+            text: text.to_owned(),
+        };
+
+        // Registered before taking the selection, so no watcher sees the change first
+        OWNER_WINDOWS.lock().unwrap().push(window);
+        owner
+            .conn
+            .set_selection_owner(window, owner.clipboard, CURRENT_TIME)?;
+        if owner
+            .conn
+            .get_selection_owner(owner.clipboard)?
+            .reply()?
+            .owner
+            != window
+        {
+            bail!("Failed to take ownership of the clipboard");
+        }
+        Ok(owner)
+    }
+
+    pub(super) fn canceller(&self) -> Canceller {
+        Canceller {
+            conn: self.conn.clone(),
+            window: self.window,
+            cancel: self.cancel,
+        }
+    }
+
+    pub(super) fn run(self) -> Result<PasteOnceResult> {
+        let result = loop {
+            match self.conn.wait_for_event()? {
+                Event::SelectionRequest(event) if self.reply(&event)? => {
+                    break PasteOnceResult::Pasted;
+                }
+                Event::SelectionClear(event) if event.selection == self.clipboard => {
+                    break PasteOnceResult::Replaced;
+                }
+                Event::ClientMessage(event) if event.type_ == self.cancel => {
+                    break PasteOnceResult::Cancelled;
+                }
+                _ => {}
+            }
+        };
+
+        // The selection owner reverts to None when its window is destroyed. Unlike
+        // SetSelectionOwner this never clears a selection another application took meanwhile
+        self.conn.destroy_window(self.window)?;
+        self.conn.sync()?;
+        Ok(result)
+    }
+
+    /// Answer a selection request, returning whether it was the paste.
+    fn reply(&self, event: &SelectionRequestEvent) -> Result<bool> {
+        // Obsolete clients pass no property and expect the target to be used
+        let property = if event.property == NONE {
+            event.target
+        } else {
+            event.property
+        };
+        let request = match self.offered.iter().find(|(atom, _)| *atom == event.target) {
+            Some((_, name)) => request(name),
+            None => Request::Unsupported,
+        };
+
+        let property = match request {
+            Request::Unsupported if event.target == self.targets => {
+                let targets: Vec<Atom> = [self.targets]
+                    .into_iter()
+                    .chain(self.offered.iter().map(|(atom, _)| *atom))
+                    .collect();
+                self.conn.change_property32(
+                    PropMode::REPLACE,
+                    event.requestor,
+                    property,
+                    AtomEnum::ATOM,
+                    &targets,
+                )?;
+                property
+            }
+            Request::Paste => {
+                self.conn.change_property8(
+                    PropMode::REPLACE,
+                    event.requestor,
+                    property,
+                    event.target,
+                    self.text.as_bytes(),
+                )?;
+                property
+            }
+            Request::Hint => {
+                self.conn.change_property8(
+                    PropMode::REPLACE,
+                    event.requestor,
+                    property,
+                    event.target,
+                    HINT_VALUE,
+                )?;
+                property
+            }
+            // Refuses the request
+            Request::Unsupported => NONE,
+        };
+
+        self.conn.send_event(
+            false,
+            event.requestor,
+            EventMask::NO_EVENT,
+            SelectionNotifyEvent {
+                response_type: SELECTION_NOTIFY_EVENT,
+                sequence: 0,
+                time: event.time,
+                requestor: event.requestor,
+                selection: event.selection,
+                target: event.target,
+                property,
+            },
+        )?;
+        self.conn.flush()?;
+        Ok(request == Request::Paste)
+    }
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        OWNER_WINDOWS
+            .lock()
+            .unwrap()
+            .retain(|window| *window != self.window);
+    }
+}
+
+/// Wakes up [Owner::run] from another thread.
+pub(super) struct Canceller {
+    conn: Arc<RustConnection>,
+    window: Window,
+    cancel: Atom,
+}
+
+impl Canceller {
+    pub(super) fn cancel(&self) {
+        // Without an event mask, the event is delivered to the client that created the window.
+        // Errors mean the owner already finished
+        let event = ClientMessageEvent::new(32, self.window, self.cancel, [0u32; 5]);
+        let _ = self
+            .conn
+            .send_event(false, self.window, EventMask::NO_EVENT, event);
+        let _ = self.conn.flush();
+    }
+}
+
+/// Reports selection owner changes through XFixes.
+pub(super) struct Watcher {
+    conn: RustConnection,
+    selections: Vec<(Atom, Selection)>,
+}
+
+impl Watcher {
+    pub(super) fn connect(selections: &[Selection]) -> Result<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        // Required before any other XFixes request
+        conn.xfixes_query_version(5, 0)?.reply()?;
+
+        let root = conn.setup().roots[screen].root;
+        let mut watched = Vec::new();
+        for selection in selections {
+            let atom = intern(
+                &conn,
+                match selection {
+                    Selection::Clipboard => "CLIPBOARD",
+                    Selection::Primary => "PRIMARY",
+                },
+            )?;
+            conn.xfixes_select_selection_input(
+                root,
+                atom,
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )?;
+            watched.push((atom, *selection));
+        }
+        conn.flush()?;
+
+        Ok(Watcher {
+            conn,
+            selections: watched,
+        })
+    }
+
+    /// Call `on_change` for every change until it returns `false`. Its second argument tells
+    /// whether the selection is now owned by a paste-once offer of this process.
+    pub(super) fn run(self, mut on_change: impl FnMut(Selection, bool) -> bool) -> Result<()> {
+        loop {
+            if let Event::XfixesSelectionNotify(event) = self.conn.wait_for_event()? {
+                let changed = self
+                    .selections
+                    .iter()
+                    .find(|(atom, _)| *atom == event.selection);
+                if let Some((_, selection)) = changed {
+                    let paste_once = OWNER_WINDOWS.lock().unwrap().contains(&event.owner);
+                    if !on_change(*selection, paste_once) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn intern(conn: &RustConnection, name: &str) -> Result<Atom> {
+    Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+}
@@ -4,8 +4,13 @@ use aes::cipher::{
     block_padding::Pkcs7, generic_array::GenericArray, typenum::U32, BlockDecryptMut,
     BlockEncryptMut, KeyIvInit,
 };
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 
-use crate::error::{CryptoError, Result};
+use crate::{
+    error::{CryptoError, Result},
+    secret::SecretBuf,
+};
 
 use super::CipherString;
 
@@ -13,14 +18,15 @@ pub fn decrypt_aes256(
     iv: &[u8; 16],
     data: &Vec<u8>,
     key: GenericArray<u8, U32>,
-) -> Result<Vec<u8>> {
+) -> Result<SecretBuf> {
     let iv = GenericArray::from_slice(iv);
-    let mut data = data.clone();
+    // Decrypted in place, so the plaintext never exists outside of secret memory
+    let mut data = SecretBuf::from_slice(data)?;
     let decrypted_key_slice = cbc::Decryptor::<aes::Aes256>::new(&key, iv)
-        .decrypt_padded_mut::<Pkcs7>(&mut data)
+        .decrypt_padded_mut::<Pkcs7>(data.as_mut_bytes())
         .map_err(|_| CryptoError::KeyDecrypt)?;
 
-    // Data is decrypted in place and returns a subslice of the original Vec, to avoid cloning it, we truncate to the subslice length
+    // Data is decrypted in place and returns a subslice of the buffer, to avoid copying it, we truncate to the subslice length
     let decrypted_len = decrypted_key_slice.len();
     data.truncate(decrypted_len);
 
@@ -37,3 +43,39 @@ pub fn encrypt_aes256(
 
     Ok(CipherString::AesCbc256_B64 { iv, data })
 }
+
+pub fn decrypt_aes256_hmac(
+    iv: &[u8; 16],
+    mac: &[u8; 32],
+    data: &Vec<u8>,
+    mac_key: GenericArray<u8, U32>,
+    key: GenericArray<u8, U32>,
+) -> Result<SecretBuf> {
+    // Compares in constant time
+    hmac_sha256(iv, data, mac_key)?
+        .verify_slice(mac)
+        .map_err(|_| CryptoError::InvalidMac)?;
+
+    decrypt_aes256(iv, data, key)
+}
+
+pub fn encrypt_aes256_hmac(
+    data_dec: &[u8],
+    iv: [u8; 16],
+    mac_key: GenericArray<u8, U32>,
+    key: GenericArray<u8, U32>,
+) -> Result<CipherString> {
+    let data = cbc::Encryptor::<aes::Aes256>::new(&key, &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(data_dec);
+    let mac = hmac_sha256(&iv, &data, mac_key)?.finalize().into_bytes().into();
+
+    Ok(CipherString::AesCbc256_HmacSha256_B64 { iv, mac, data })
+}
+
+fn hmac_sha256(iv: &[u8; 16], data: &[u8], mac_key: GenericArray<u8, U32>) -> Result<Hmac<Sha256>> {
+    let mut hmac =
+        Hmac::<Sha256>::new_from_slice(&mac_key).map_err(|_| CryptoError::InvalidMac)?;
+    hmac.update(iv);
+    hmac.update(data);
+    Ok(hmac)
+}
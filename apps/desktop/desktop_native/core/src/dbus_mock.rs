@@ -0,0 +1,29 @@
+//! Helpers for testing D-Bus clients against mock services without a running bus daemon.
+
+use std::os::unix::net::UnixStream;
+
+use zbus::{connection::Builder, Connection, Guid};
+
+/// Connect a client to a server on a private peer-to-peer bus. `serve` registers the mock
+/// interfaces on the server, e.g. `|b| b.serve_at("/path", MockService::default())`.
+///
+/// Returns the client and server connections. The server must be kept alive for the duration of
+/// the test, it is also used to emit signals.
+pub async fn connect<F>(serve: F) -> (Connection, Connection)
+where
+    F: FnOnce(Builder<'static>) -> zbus::Result<Builder<'static>>,
+{
+    let (client, server) = UnixStream::pair().unwrap();
+
+    let server = serve(
+        Builder::unix_stream(server)
+            .server(Guid::generate())
+            .unwrap()
+            .p2p(),
+    )
+    .unwrap();
+    let client = Builder::unix_stream(client).p2p();
+
+    let (client, server) = tokio::join!(client.build(), server.build());
+    (client.unwrap(), server.unwrap())
+}
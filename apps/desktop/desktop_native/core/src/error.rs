@@ -9,6 +9,9 @@ pub enum Error {
 
     #[error("Cryptography Error, {0}")]
     Crypto(#[from] CryptoError),
+
+    #[error("Unable to allocate secret memory: {0}")]
+    SecretAlloc(std::io::Error),
 }
 
 #[derive(Debug, Error)]
@@ -27,6 +30,8 @@ pub enum CSParseError {
 pub enum CryptoError {
     #[error("Error while decrypting cipher string")]
     KeyDecrypt,
+    #[error("Message authentication code does not match")]
+    InvalidMac,
 }
 
 // Ensure that the error messages implement Send and Sync
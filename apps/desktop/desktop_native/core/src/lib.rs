@@ -3,5 +3,10 @@ pub mod clipboard;
 pub mod crypto;
 pub mod error;
 pub mod password;
+pub mod pin;
 pub mod process_isolation;
 pub mod powermonitor;
+pub mod secret;
+
+#[cfg(all(test, target_os = "linux"))]
+mod dbus_mock;
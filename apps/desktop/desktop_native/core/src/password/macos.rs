@@ -2,13 +2,25 @@ use anyhow::Result;
 use security_framework::passwords::{
     delete_generic_password, get_generic_password, set_generic_password,
 };
+use security_framework_sys::base::errSecItemNotFound;
 
-pub fn get_password(service: &str, account: &str) -> Result<String> {
-    let result = String::from_utf8(get_generic_password(&service, &account)?)?;
+use super::PasswordNotFound;
+use crate::secret::SecretBuf;
+
+pub fn get_password(service: &str, account: &str) -> Result<SecretBuf> {
+    let password = get_generic_password(&service, &account).map_err(|e| -> anyhow::Error {
+        if e.code() == errSecItemNotFound {
+            PasswordNotFound(e.to_string()).into()
+        } else {
+            e.into()
+        }
+    })?;
+    let result = SecretBuf::from_vec(password)?;
+    result.as_str()?;
     Ok(result)
 }
 
-pub fn get_password_keytar(service: &str, account: &str) -> Result<String> {
+pub fn get_password_keytar(service: &str, account: &str) -> Result<SecretBuf> {
     get_password(service, account)
 }
 
@@ -35,8 +47,8 @@ mod tests {
         scopeguard::defer!(delete_password("BitwardenTest", "BitwardenTest").unwrap_or({}););
         set_password("BitwardenTest", "BitwardenTest", "Random").unwrap();
         assert_eq!(
-            "Random",
-            get_password("BitwardenTest", "BitwardenTest").unwrap()
+            get_password("BitwardenTest", "BitwardenTest").unwrap(),
+            "Random"
         );
         delete_password("BitwardenTest", "BitwardenTest").unwrap();
 
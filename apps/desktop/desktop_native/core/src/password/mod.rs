@@ -3,3 +3,23 @@
 #[cfg_attr(target_os = "macos", path = "macos.rs")]
 mod password;
 pub use password::*;
+
+use anyhow::Result;
+
+use crate::secret::SecretBuf;
+
+/// Returned by `get_password` if nothing is stored for the service and account, as opposed to the
+/// keychain being unavailable.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct PasswordNotFound(pub String);
+
+/// Fetch the stored password, `None` if there is none. Other errors, like a locked keychain, are
+/// returned as is, so callers do not mistake them for a missing entry.
+pub fn find_password(service: &str, account: &str) -> Result<Option<SecretBuf>> {
+    match get_password(service, account) {
+        Ok(password) => Ok(Some(password)),
+        Err(e) if e.is::<PasswordNotFound>() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -1,8 +1,11 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use libsecret::{password_clear_sync, password_lookup_sync, password_store_sync, Schema};
 use std::collections::HashMap;
 
-pub fn get_password(service: &str, account: &str) -> Result<String> {
+use super::PasswordNotFound;
+use crate::secret::SecretBuf;
+
+pub fn get_password(service: &str, account: &str) -> Result<SecretBuf> {
     let res = password_lookup_sync(
         Some(&get_schema()),
         build_attributes(service, account),
@@ -10,12 +13,12 @@ pub fn get_password(service: &str, account: &str) -> Result<String> {
     )?;
 
     match res {
-        Some(s) => Ok(String::from(s)),
-        None => Err(anyhow!("No password found")),
+        Some(s) => Ok(SecretBuf::from_slice(s.as_bytes())?),
+        None => Err(PasswordNotFound("No password found".to_owned()).into()),
     }
 }
 
-pub fn get_password_keytar(service: &str, account: &str) -> Result<String> {
+pub fn get_password_keytar(service: &str, account: &str) -> Result<SecretBuf> {
     get_password(service, account)
 }
 
@@ -80,8 +83,8 @@ mod tests {
         scopeguard::defer!(delete_password("BitwardenTest", "BitwardenTest").unwrap_or({}););
         set_password("BitwardenTest", "BitwardenTest", "Random").unwrap();
         assert_eq!(
-            "Random",
-            get_password("BitwardenTest", "BitwardenTest").unwrap()
+            get_password("BitwardenTest", "BitwardenTest").unwrap(),
+            "Random"
         );
         delete_password("BitwardenTest", "BitwardenTest").unwrap();
 
@@ -11,9 +11,12 @@ use windows::{
     },
 };
 
+use super::PasswordNotFound;
+use crate::secret::SecretBuf;
+
 const CRED_FLAGS_NONE: u32 = 0;
 
-pub fn get_password<'a>(service: &str, account: &str) -> Result<String> {
+pub fn get_password<'a>(service: &str, account: &str) -> Result<SecretBuf> {
     let target_name = U16CString::from_str(target_name(service, account))?;
 
     let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
@@ -32,7 +35,7 @@ pub fn get_password<'a>(service: &str, account: &str) -> Result<String> {
         unsafe { CredFree(credential as *mut _) };
     });
 
-    result.map_err(|e| anyhow!(convert_error(e)))?;
+    result.map_err(convert_error)?;
 
     let password = unsafe {
         U16String::from_ptr(
@@ -42,11 +45,11 @@ pub fn get_password<'a>(service: &str, account: &str) -> Result<String> {
         .to_string_lossy()
     };
 
-    Ok(String::from(password))
+    Ok(SecretBuf::from_string(password)?)
 }
 
 // Remove this after sufficient releases
-pub fn get_password_keytar<'a>(service: &str, account: &str) -> Result<String> {
+pub fn get_password_keytar<'a>(service: &str, account: &str) -> Result<SecretBuf> {
     let target_name = U16CString::from_str(target_name(service, account))?;
 
     let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
@@ -68,13 +71,13 @@ pub fn get_password_keytar<'a>(service: &str, account: &str) -> Result<String> {
     result?;
 
     let password = unsafe {
-        std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+        std::slice::from_raw_parts(
             (*credential).CredentialBlob,
             (*credential).CredentialBlobSize as usize,
-        ))
+        )
     };
 
-    Ok(String::from(password))
+    Ok(SecretBuf::from_slice(password)?)
 }
 
 pub fn set_password(service: &str, account: &str, password: &str) -> Result<()> {
@@ -131,11 +134,11 @@ fn target_name(service: &str, account: &str) -> String {
 }
 
 // Convert the internal WIN32 errors to descriptive messages
-fn convert_error(e: windows::core::Error) -> String {
+fn convert_error(e: windows::core::Error) -> anyhow::Error {
     if e == ERROR_NOT_FOUND.into() {
-        return "Password not found.".to_string();
+        return PasswordNotFound("Password not found.".to_string()).into();
     }
-    e.to_string()
+    anyhow!(e.to_string())
 }
 
 #[cfg(test)]
@@ -147,8 +150,8 @@ mod tests {
         scopeguard::defer!(delete_password("BitwardenTest", "BitwardenTest").unwrap_or({}););
         set_password("BitwardenTest", "BitwardenTest", "Random").unwrap();
         assert_eq!(
-            "Random",
-            get_password("BitwardenTest", "BitwardenTest").unwrap()
+            get_password("BitwardenTest", "BitwardenTest").unwrap(),
+            "Random"
         );
         delete_password("BitwardenTest", "BitwardenTest").unwrap();
 
@@ -164,8 +167,8 @@ mod tests {
         scopeguard::defer!(delete_password("BitwardenTest", "BitwardenTest").unwrap_or({}););
         keytar::set_password("BitwardenTest", "BitwardenTest", "HelloFromKeytar").unwrap();
         assert_eq!(
-            "HelloFromKeytar",
-            get_password_keytar("BitwardenTest", "BitwardenTest").unwrap()
+            get_password_keytar("BitwardenTest", "BitwardenTest").unwrap(),
+            "HelloFromKeytar"
         );
     }
 
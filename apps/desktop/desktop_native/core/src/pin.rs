@@ -0,0 +1,413 @@
+use std::sync::Mutex;
+
+use aes::cipher::generic_array::GenericArray;
+use anyhow::{anyhow, bail, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    crypto::{self, CipherString},
+    error::{CryptoError, Error},
+    secret::SecretBuf,
+};
+
+/// Used when the attempt counter of an existing PIN is missing.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Held while an unlock reads and updates the attempt counter, so concurrent attempts can't
+/// overwrite each other's count.
+static UNLOCK_LOCK: Mutex<()> = Mutex::new(());
+
+/// The KDF settings of the account, as returned by the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    Pbkdf2 {
+        iterations: u32,
+    },
+    Argon2id {
+        iterations: u32,
+        /// Memory in MiB.
+        memory: u32,
+        parallelism: u32,
+    },
+}
+
+/// Protect `user_key_b64` with a key derived from `pin`, allowing `max_attempts` failed unlocks
+/// before the protected key is deleted. Replaces an existing PIN.
+///
+/// `email` is the account email used as KDF salt, the same way the master password key is derived.
+pub fn set_pin(
+    service: &str,
+    account: &str,
+    pin: &str,
+    email: &str,
+    kdf: &Kdf,
+    user_key_b64: &str,
+    max_attempts: u32,
+) -> Result<()> {
+    if max_attempts == 0 {
+        bail!("At least one PIN attempt must be allowed");
+    }
+
+    let user_key = base64_engine.decode(user_key_b64)?;
+    let protected = protect(&user_key, &PinKey::derive(pin, email, kdf)?)?;
+
+    // Reset the counter first, so a stale counter never applies to the new PIN
+    AttemptCounter {
+        failed: 0,
+        max: max_attempts,
+    }
+    .store(service, account)?;
+    crate::password::set_password(service, account, &protected.to_string())
+}
+
+/// Decrypt the user key protected by [set_pin], returning it base64 encoded.
+///
+/// Every failed attempt is counted, once the limit is reached the protected key is deleted and
+/// the PIN can no longer be used.
+pub fn unlock_with_pin(
+    service: &str,
+    account: &str,
+    pin: &str,
+    email: &str,
+    kdf: &Kdf,
+) -> Result<SecretBuf> {
+    let _lock = UNLOCK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let protected: CipherString = crate::password::find_password(service, account)?
+        .ok_or(anyhow!("PIN unlock is not set up"))?
+        .as_str()?
+        .parse()?;
+    let mut counter = AttemptCounter::load(service, account)?;
+
+    // Count the attempt before decrypting, so killing the process mid-attempt does not reset it
+    counter.failed += 1;
+    counter.store(service, account)?;
+
+    match unprotect(&protected, &PinKey::derive(pin, email, kdf)?) {
+        Ok(user_key) => {
+            counter.failed = 0;
+            counter.store(service, account)?;
+            encode_secret(&user_key)
+        }
+        Err(Error::Crypto(CryptoError::InvalidMac)) if counter.failed >= counter.max => {
+            remove_pin(service, account)?;
+            Err(anyhow!(
+                "Too many failed PIN attempts, PIN unlock has been disabled"
+            ))
+        }
+        Err(Error::Crypto(CryptoError::InvalidMac)) => Err(anyhow!(
+            "Invalid PIN, {} attempts remaining",
+            counter.max - counter.failed
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn has_pin(service: &str, account: &str) -> Result<bool> {
+    Ok(crate::password::find_password(service, account)?.is_some())
+}
+
+/// Number of unlock attempts left before the protected key is deleted.
+pub fn remaining_attempts(service: &str, account: &str) -> Result<u32> {
+    if !has_pin(service, account)? {
+        return Ok(0);
+    }
+    let counter = AttemptCounter::load(service, account)?;
+    Ok(counter.max.saturating_sub(counter.failed))
+}
+
+pub fn remove_pin(service: &str, account: &str) -> Result<()> {
+    // Either entry may already be missing, e.g. after an interrupted removal
+    let deleted = crate::password::delete_password(service, account);
+    let _ = crate::password::delete_password(service, &attempts_account(account));
+    if has_pin(service, account)? {
+        return deleted;
+    }
+    Ok(())
+}
+
+/// The stretched PIN key, matching the PIN key of the other Bitwarden clients.
+struct PinKey {
+    enc: [u8; 32],
+    mac: [u8; 32],
+}
+
+impl PinKey {
+    fn derive(pin: &str, email: &str, kdf: &Kdf) -> Result<Self> {
+        let mut key = [0u8; 32];
+        match *kdf {
+            Kdf::Pbkdf2 { iterations } => pbkdf2::pbkdf2_hmac::<Sha256>(
+                pin.as_bytes(),
+                email.as_bytes(),
+                iterations,
+                &mut key,
+            ),
+            Kdf::Argon2id {
+                iterations,
+                memory,
+                parallelism,
+            } => {
+                let memory = memory
+                    .checked_mul(1024)
+                    .ok_or(anyhow!("Argon2 memory of {} MiB is too large", memory))?;
+                let params = Params::new(memory, iterations, parallelism, Some(32))
+                    .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))?;
+                // Argon2 requires a salt of at least 8 bytes, so the email is hashed
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(pin.as_bytes(), &Sha256::digest(email), &mut key)
+                    .map_err(|e| anyhow!("Failed to derive PIN key: {}", e))?;
+            }
+        }
+
+        // Stretch the KDF output into separate encryption and mac keys
+        let hkdf = Hkdf::<Sha256>::from_prk(&key)
+            .map_err(|e| anyhow!("Failed to stretch PIN key: {}", e))?;
+        let mut pin_key = PinKey {
+            enc: [0u8; 32],
+            mac: [0u8; 32],
+        };
+        hkdf.expand(b"enc", &mut pin_key.enc)
+            .and_then(|_| hkdf.expand(b"mac", &mut pin_key.mac))
+            .map_err(|e| anyhow!("Failed to stretch PIN key: {}", e))?;
+        Ok(pin_key)
+    }
+}
+
+fn protect(user_key: &[u8], pin_key: &PinKey) -> Result<CipherString> {
+    Ok(crypto::encrypt_aes256_hmac(
+        user_key,
+        rand::random(),
+        GenericArray::clone_from_slice(&pin_key.mac),
+        GenericArray::clone_from_slice(&pin_key.enc),
+    )?)
+}
+
+fn unprotect(protected: &CipherString, pin_key: &PinKey) -> crate::error::Result<SecretBuf> {
+    match protected {
+        CipherString::AesCbc256_HmacSha256_B64 { iv, mac, data } => crypto::decrypt_aes256_hmac(
+            iv,
+            mac,
+            data,
+            GenericArray::clone_from_slice(&pin_key.mac),
+            GenericArray::clone_from_slice(&pin_key.enc),
+        ),
+        _ => Err(CryptoError::KeyDecrypt.into()),
+    }
+}
+
+/// Base64 encode `data` without the encoded secret passing through a `String`.
+fn encode_secret(data: &[u8]) -> Result<SecretBuf> {
+    let mut encoded = SecretBuf::new(base64::encoded_len(data.len(), true).unwrap_or_default())?;
+    let len = base64_engine.encode_slice(data, encoded.as_mut_bytes())?;
+    encoded.truncate(len);
+    Ok(encoded)
+}
+
+fn attempts_account(account: &str) -> String {
+    format!("{}_pin_attempts", account)
+}
+
+/// Failed unlock attempts, stored next to the protected key as `failed/max`.
+#[derive(Debug, PartialEq, Eq)]
+struct AttemptCounter {
+    failed: u32,
+    max: u32,
+}
+
+impl AttemptCounter {
+    fn load(service: &str, account: &str) -> Result<Self> {
+        // Any other failure must not reset the count, so only a missing entry gets the defaults
+        match crate::password::find_password(service, &attempts_account(account))? {
+            Some(counter) => counter.as_str()?.parse(),
+            None => Ok(AttemptCounter {
+                failed: 0,
+                max: DEFAULT_MAX_ATTEMPTS,
+            }),
+        }
+    }
+
+    fn store(&self, service: &str, account: &str) -> Result<()> {
+        crate::password::set_password(service, &attempts_account(account), &self.to_string())
+    }
+}
+
+impl std::fmt::Display for AttemptCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.failed, self.max)
+    }
+}
+
+impl std::str::FromStr for AttemptCounter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (failed, max) = s
+            .split_once('/')
+            .ok_or(anyhow!("Invalid PIN attempt counter"))?;
+        Ok(AttemptCounter {
+            failed: failed.parse()?,
+            max: max.parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KDF: Kdf = Kdf::Pbkdf2 { iterations: 1000 };
+
+    #[test]
+    fn protect_and_unprotect() {
+        let pin_key = PinKey::derive("1234", "user@example.com", &KDF).unwrap();
+        let protected = protect(&[7u8; 64], &pin_key).unwrap();
+        assert!(matches!(
+            protected,
+            CipherString::AesCbc256_HmacSha256_B64 { .. }
+        ));
+
+        let protected: CipherString = protected.to_string().parse().unwrap();
+        assert_eq!(
+            unprotect(&protected, &pin_key).unwrap().as_bytes(),
+            [7u8; 64]
+        );
+    }
+
+    #[test]
+    fn unprotect_with_wrong_pin() {
+        let pin_key = PinKey::derive("1234", "user@example.com", &KDF).unwrap();
+        let protected = protect(&[7u8; 64], &pin_key).unwrap();
+
+        let wrong_key = PinKey::derive("1235", "user@example.com", &KDF).unwrap();
+        assert!(matches!(
+            unprotect(&protected, &wrong_key),
+            Err(Error::Crypto(CryptoError::InvalidMac))
+        ));
+    }
+
+    #[test]
+    fn derive_depends_on_salt_and_kdf() {
+        let key = PinKey::derive("1234", "user@example.com", &KDF).unwrap();
+        let other_salt = PinKey::derive("1234", "other@example.com", &KDF).unwrap();
+        let argon2 = PinKey::derive(
+            "1234",
+            "user@example.com",
+            &Kdf::Argon2id {
+                iterations: 1,
+                memory: 1,
+                parallelism: 1,
+            },
+        )
+        .unwrap();
+
+        assert_ne!(key.enc, other_salt.enc);
+        assert_ne!(key.enc, argon2.enc);
+        assert_ne!(key.enc, key.mac);
+    }
+
+    #[test]
+    fn derive_rejects_overflowing_memory() {
+        let kdf = Kdf::Argon2id {
+            iterations: 1,
+            memory: u32::MAX,
+            parallelism: 1,
+        };
+        assert!(PinKey::derive("1234", "user@example.com", &kdf).is_err());
+    }
+
+    #[test]
+    fn encode_secret_matches_base64() {
+        for data in [&b""[..], b"a", b"ab", b"abc", &[7u8; 64]] {
+            assert_eq!(
+                encode_secret(data).unwrap(),
+                base64_engine.encode(data).as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn attempt_counter_roundtrip() {
+        let counter = AttemptCounter { failed: 2, max: 5 };
+        assert_eq!(
+            counter.to_string().parse::<AttemptCounter>().unwrap(),
+            counter
+        );
+        assert!("2".parse::<AttemptCounter>().is_err());
+    }
+
+    #[test]
+    fn unlock_with_pin_counts_and_resets_attempts() {
+        scopeguard::defer! {
+            remove_pin("BitwardenTest", "PinReset").unwrap();
+        }
+        let user_key_b64 = base64_engine.encode([7u8; 64]);
+        set_pin(
+            "BitwardenTest",
+            "PinReset",
+            "1234",
+            "user@example.com",
+            &KDF,
+            &user_key_b64,
+            3,
+        )
+        .unwrap();
+
+        let result = unlock_with_pin(
+            "BitwardenTest",
+            "PinReset",
+            "0000",
+            "user@example.com",
+            &KDF,
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid PIN, 2 attempts remaining"
+        );
+        assert_eq!(remaining_attempts("BitwardenTest", "PinReset").unwrap(), 2);
+
+        let result = unlock_with_pin(
+            "BitwardenTest",
+            "PinReset",
+            "1234",
+            "user@example.com",
+            &KDF,
+        );
+        assert_eq!(result.unwrap(), user_key_b64.as_str());
+        assert_eq!(remaining_attempts("BitwardenTest", "PinReset").unwrap(), 3);
+    }
+
+    #[test]
+    fn unlock_with_pin_wipes_key_after_max_attempts() {
+        scopeguard::defer! {
+            remove_pin("BitwardenTest", "PinWipe").unwrap();
+        }
+        let user_key_b64 = base64_engine.encode([7u8; 64]);
+        set_pin(
+            "BitwardenTest",
+            "PinWipe",
+            "1234",
+            "user@example.com",
+            &KDF,
+            &user_key_b64,
+            2,
+        )
+        .unwrap();
+
+        assert!(
+            unlock_with_pin("BitwardenTest", "PinWipe", "0000", "user@example.com", &KDF).is_err()
+        );
+        let result = unlock_with_pin("BitwardenTest", "PinWipe", "0000", "user@example.com", &KDF);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Too many failed PIN attempts, PIN unlock has been disabled"
+        );
+        assert!(!has_pin("BitwardenTest", "PinWipe").unwrap());
+
+        // The correct PIN no longer works either
+        assert!(
+            unlock_with_pin("BitwardenTest", "PinWipe", "1234", "user@example.com", &KDF).is_err()
+        );
+    }
+}
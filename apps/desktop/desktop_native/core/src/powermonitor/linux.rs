@@ -1,51 +1,1555 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap, sync::Mutex, time::Duration};
 
-use zbus::{Connection, MatchRule, export::futures_util::TryStreamExt};
+use x11rb::{
+    connection::Connection as _,
+    protocol::{screensaver::ConnectionExt as _, xproto::Window},
+    rust_connection::RustConnection,
+};
+use zbus::{
+    export::futures_util::{stream, TryStreamExt},
+    names::InterfaceName,
+    zvariant::{ObjectPath, OwnedFd, OwnedObjectPath, OwnedValue},
+    Connection, MatchRule, Message, MessageStream,
+};
+
+use super::{IdleEvent, IdleSubscription, LockEvent, LockSubscription};
+
+#[derive(Clone)]
 struct ScreenLock {
-    interface: Cow<'static, str>,
+    service: Cow<'static, str>,
     path: Cow<'static, str>,
+    /// Implements `GetActive` and the `ActiveChanged(b)` signal.
+    interface: Cow<'static, str>,
 }
 
-const SCREEN_LOCK_MONITORS: [ScreenLock; 2] = [
-    ScreenLock {
-        interface: Cow::Borrowed("org.gnome.ScreenSaver"),
-        path: Cow::Borrowed("/org/gnome/ScreenSaver"),
-    },
-    ScreenLock {
-        interface: Cow::Borrowed("org.freedesktop.ScreenSaver"),
-        path: Cow::Borrowed("/org/freedesktop/ScreenSaver"),
-    },
+impl ScreenLock {
+    const fn new(service: &'static str, path: &'static str, interface: &'static str) -> Self {
+        ScreenLock {
+            service: Cow::Borrowed(service),
+            path: Cow::Borrowed(path),
+            interface: Cow::Borrowed(interface),
+        }
+    }
+}
+
+const SCREEN_LOCK_MONITORS: [ScreenLock; 6] = [
+    ScreenLock::new(
+        "org.gnome.ScreenSaver",
+        "/org/gnome/ScreenSaver",
+        "org.gnome.ScreenSaver",
+    ),
+    ScreenLock::new(
+        "org.freedesktop.ScreenSaver",
+        "/org/freedesktop/ScreenSaver",
+        "org.freedesktop.ScreenSaver",
+    ),
+    // KDE implements the freedesktop interface under its own name
+    ScreenLock::new(
+        "org.kde.screensaver",
+        "/ScreenSaver",
+        "org.freedesktop.ScreenSaver",
+    ),
+    ScreenLock::new(
+        "org.cinnamon.ScreenSaver",
+        "/org/cinnamon/ScreenSaver",
+        "org.cinnamon.ScreenSaver",
+    ),
+    ScreenLock::new(
+        "org.mate.ScreenSaver",
+        "/org/mate/ScreenSaver",
+        "org.mate.ScreenSaver",
+    ),
+    ScreenLock::new(
+        "org.xfce.ScreenSaver",
+        "/org/xfce/ScreenSaver",
+        "org.xfce.ScreenSaver",
+    ),
 ];
 
-pub async fn on_lock(tx: tokio::sync::mpsc::Sender<()>) -> Result<(), Box<dyn std::error::Error>> {
+static EXTRA_SCREEN_LOCK_MONITORS: Mutex<Vec<ScreenLock>> = Mutex::new(Vec::new());
+
+/// Also watch the screen saver `interface` at `path`, served under the name of the interface.
+/// Applies to subscriptions and checks started after this call.
+pub fn add_screen_lock_monitor(
+    interface: &str,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    InterfaceName::try_from(interface)?;
+    ObjectPath::try_from(path)?;
+
+    let mut extra = EXTRA_SCREEN_LOCK_MONITORS.lock().unwrap();
+    if screen_lock_monitors_with(&extra)
+        .iter()
+        .any(|monitor| monitor.interface == interface && monitor.path == path)
+    {
+        return Ok(());
+    }
+    extra.push(ScreenLock {
+        service: Cow::Owned(interface.to_owned()),
+        path: Cow::Owned(path.to_owned()),
+        interface: Cow::Owned(interface.to_owned()),
+    });
+    Ok(())
+}
+
+/// The built-in screen savers followed by those added with [add_screen_lock_monitor].
+fn screen_lock_monitors() -> Vec<ScreenLock> {
+    screen_lock_monitors_with(&EXTRA_SCREEN_LOCK_MONITORS.lock().unwrap())
+}
+
+fn screen_lock_monitors_with(extra: &[ScreenLock]) -> Vec<ScreenLock> {
+    SCREEN_LOCK_MONITORS
+        .into_iter()
+        .chain(extra.iter().cloned())
+        .collect()
+}
+
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+const LOGIND_SESSION: &str = "org.freedesktop.login1.Session";
+const DBUS_PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+const PORTAL_SERVICE: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_INHIBIT: &str = "org.freedesktop.portal.Inhibit";
+const PORTAL_REQUEST: &str = "org.freedesktop.portal.Request";
+/// The `session-state` of the Inhibit portal when the session is about to end.
+const PORTAL_SESSION_QUERY_END: u32 = 2;
+
+const MUTTER_IDLE_MONITOR: &str = "org.gnome.Mutter.IdleMonitor";
+const MUTTER_IDLE_MONITOR_PATH: &str = "/org/gnome/Mutter/IdleMonitor/Core";
+const FREEDESKTOP_SCREEN_SAVER: &str = "org.freedesktop.ScreenSaver";
+const FREEDESKTOP_SCREEN_SAVER_PATH: &str = "/org/freedesktop/ScreenSaver";
+
+/// How often the idle time is read where the desktop offers no idle watches.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long sleep is delayed for a suspend to be acknowledged. logind itself stops waiting after
+/// `InhibitDelayMaxSec`, 5 seconds by default.
+const SLEEP_DELAY_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub async fn on_lock(
+    tx: tokio::sync::mpsc::Sender<LockEvent>,
+) -> Result<LockSubscription, Box<dyn std::error::Error>> {
+    let connection = Connection::session().await?;
+    // logind is optional, the system bus may not be reachable, e.g. from a confined Snap
+    let system = Connection::system().await.ok();
+    listen(&connection, system.as_ref(), tx).await
+}
+
+async fn listen(
+    connection: &Connection,
+    system: Option<&Connection>,
+    tx: tokio::sync::mpsc::Sender<LockEvent>,
+) -> Result<LockSubscription, Box<dyn std::error::Error>> {
+    // A single stream keeps the events of all screen savers in order
+    let match_rule = MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .member("ActiveChanged")?
+        .build();
+    let screen_savers = MessageStream::for_match_rule(match_rule, connection, None).await?;
+    let monitors = screen_lock_monitors();
+
+    let (session, logind) = match system {
+        Some(system) => match logind_messages(system).await? {
+            Some((session, messages)) => (Some(session), Some(messages)),
+            None => (None, None),
+        },
+        None => (None, None),
+    };
+
+    // Reports logout on desktops where logind is unreachable, e.g. from a confined Snap or Flatpak
+    let match_rule = MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .path_namespace(PORTAL_PATH)?
+        .build();
+    let portal_messages = MessageStream::for_match_rule(match_rule, connection, None).await?;
+    let mut portal = PortalSessionMonitor::create(connection).await?;
+    let portal_messages = portal.as_ref().map(|_| portal_messages);
+
+    let mut messages = stream::select_all(
+        [Some(screen_savers), logind, portal_messages]
+            .into_iter()
+            .flatten(),
+    );
+
+    // Sleep is only delayed while logind is in use
+    let system = session.as_ref().and(system).cloned();
+    let mut inhibitor = match &system {
+        Some(system) => inhibit_sleep(system).await?,
+        None => None,
+    };
+    let (suspend_acks, mut acks) = tokio::sync::mpsc::channel(1);
+
+    // Dropping the streams removes the match rules again, dropping the inhibitor lets the system
+    // sleep
+    let task = tokio::spawn(async move {
+        loop {
+            let message = tokio::select! {
+                message = messages.try_next() => message,
+                _ = tx.closed() => break,
+            };
+            let Ok(Some(message)) = message else {
+                break;
+            };
+            let event = match &mut portal {
+                Some(portal) if portal.is_portal_message(&message) => {
+                    portal.session_event(&message).await
+                }
+                _ => lock_event(&message, &monitors, session.as_ref()),
+            };
+            let Some(event) = event else {
+                continue;
+            };
+            let suspend = matches!(event, LockEvent::Suspend { .. });
+            let resume = matches!(event, LockEvent::Resume { .. });
+            if suspend {
+                // An acknowledgement of an earlier suspend must not release this one
+                while acks.try_recv().is_ok() {}
+            }
+            if tx.send(event).await.is_err() {
+                break;
+            }
+            if suspend && inhibitor.is_some() {
+                wait_for_suspend_ack(&mut acks).await;
+                inhibitor = None;
+            }
+            if resume && inhibitor.is_none() {
+                if let Some(system) = &system {
+                    inhibitor = inhibit_sleep(system).await.ok().flatten();
+                }
+            }
+        }
+    });
+
+    Ok(LockSubscription { task, suspend_acks })
+}
+
+/// Take a logind `delay` inhibitor lock for sleep, which is held until the returned descriptor is
+/// closed. Returns `None` if logind refuses it.
+async fn inhibit_sleep(
+    connection: &Connection,
+) -> Result<Option<OwnedFd>, Box<dyn std::error::Error>> {
+    let reply = connection
+        .call_method(
+            Some(LOGIND_SERVICE),
+            LOGIND_PATH,
+            Some(LOGIND_MANAGER),
+            "Inhibit",
+            &(
+                "sleep",
+                "Bitwarden",
+                "Lock the vault before the system sleeps",
+                "delay",
+            ),
+        )
+        .await;
+    match reply {
+        Ok(reply) => Ok(Some(reply.body().deserialize()?)),
+        Err(zbus::Error::MethodError(..)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Wait until the suspend is acknowledged, but no longer than [SLEEP_DELAY_TIMEOUT].
+async fn wait_for_suspend_ack(acks: &mut tokio::sync::mpsc::Receiver<()>) {
+    let acknowledged = async {
+        // Once the subscription is dropped nobody can acknowledge, the timeout still gives the
+        // event handler time to run
+        if acks.recv().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+    };
+    let _ = tokio::time::timeout(SLEEP_DELAY_TIMEOUT, acknowledged).await;
+}
+
+/// Subscribe to the signals of logind and our session. Returns `None` if logind is not running
+/// or this process does not belong to a session.
+async fn logind_messages(
+    connection: &Connection,
+) -> Result<Option<(OwnedObjectPath, MessageStream)>, Box<dyn std::error::Error>> {
+    let Some(session) = logind_session(connection).await? else {
+        return Ok(None);
+    };
+    // Covers both the manager and the session objects. Limited to logind, so other clients
+    // cannot fake a lock or suspend under its path
+    let match_rule = MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .sender(LOGIND_SERVICE)?
+        .path_namespace(LOGIND_PATH)?
+        .build();
+    let messages = MessageStream::for_match_rule(match_rule, connection, None).await?;
+    Ok(Some((session, messages)))
+}
+
+/// The object path of the logind session of this process, or of the user's display session.
+async fn logind_session(
+    connection: &Connection,
+) -> Result<Option<OwnedObjectPath>, Box<dyn std::error::Error>> {
+    let reply = connection
+        .call_method(
+            Some(LOGIND_SERVICE),
+            LOGIND_PATH,
+            Some(LOGIND_MANAGER),
+            "GetSession",
+            &"auto",
+        )
+        .await;
+    match reply {
+        Ok(reply) => Ok(Some(reply.body().deserialize()?)),
+        // logind is missing, denied access or there is no session
+        Err(zbus::Error::MethodError(..)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse an `ActiveChanged(b)` signal of a screen saver, `Lock`, `Unlock` and changes of `Active`
+/// of our logind session or `PrepareForSleep(b)` and `PrepareForShutdown(b)` of logind. Other
+/// messages are ignored.
+fn lock_event(
+    message: &Message,
+    monitors: &[ScreenLock],
+    session: Option<&OwnedObjectPath>,
+) -> Option<LockEvent> {
+    let header = message.header();
+    let interface = header.interface()?;
+    let source = interface.to_string();
+
+    match (interface.as_str(), header.member()?.as_str()) {
+        (LOGIND_SESSION, member @ ("Lock" | "Unlock")) => {
+            // logind signals the lock of every session, not only ours
+            if header.path()?.as_str() != session?.as_str() {
+                return None;
+            }
+            match member {
+                "Lock" => Some(LockEvent::Locked { source }),
+                _ => Some(LockEvent::Unlocked { source }),
+            }
+        }
+        (DBUS_PROPERTIES, "PropertiesChanged") => {
+            if header.path()?.as_str() != session?.as_str() {
+                return None;
+            }
+            let (interface, changed, _) = message
+                .body()
+                .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+                .ok()?;
+            if interface != LOGIND_SESSION {
+                return None;
+            }
+            // Another session is active, e.g. after switching users
+            match changed.get("Active")?.downcast_ref::<bool>().ok()? {
+                false => Some(LockEvent::SessionInactive {
+                    source: LOGIND_SESSION.to_owned(),
+                }),
+                true => None,
+            }
+        }
+        (LOGIND_MANAGER, "PrepareForSleep") => match message.body().deserialize::<bool>().ok()? {
+            true => Some(LockEvent::Suspend { source }),
+            false => Some(LockEvent::Resume { source }),
+        },
+        // `false` when a shutdown is cancelled
+        (LOGIND_MANAGER, "PrepareForShutdown") => {
+            match message.body().deserialize::<bool>().ok()? {
+                true => Some(LockEvent::SessionEnding { source }),
+                false => None,
+            }
+        }
+        (interface, "ActiveChanged")
+            if monitors
+                .iter()
+                .any(|monitor| monitor.interface == interface) =>
+        {
+            match message.body().deserialize::<bool>().ok()? {
+                true => Some(LockEvent::Locked { source }),
+                false => Some(LockEvent::Unlocked { source }),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Session monitoring through the Inhibit portal, see
+/// <https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.Inhibit.html>.
+struct PortalSessionMonitor {
+    connection: Connection,
+    /// The request of `CreateMonitor`, which answers with the monitor session.
+    request: OwnedObjectPath,
+    session: Option<String>,
+}
+
+impl PortalSessionMonitor {
+    /// Ask the portal to monitor the session. Returns `None` where the portal is missing.
+    async fn create(connection: &Connection) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let options: HashMap<&str, OwnedValue> = HashMap::new();
+        let reply = connection
+            .call_method(
+                Some(PORTAL_SERVICE),
+                PORTAL_PATH,
+                Some(PORTAL_INHIBIT),
+                "CreateMonitor",
+                &("", options),
+            )
+            .await;
+        let request = match reply {
+            Ok(reply) => reply.body().deserialize()?,
+            Err(zbus::Error::MethodError(..)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(PortalSessionMonitor {
+            connection: connection.clone(),
+            request,
+            session: None,
+        }))
+    }
+
+    fn is_portal_message(&self, message: &Message) -> bool {
+        message
+            .header()
+            .path()
+            .is_some_and(|path| path.as_str().starts_with(PORTAL_PATH))
+    }
+
+    /// Handle the `Response` to `CreateMonitor` and `StateChanged` signals. The portal expects
+    /// every query to end to be answered within a second.
+    async fn session_event(&mut self, message: &Message) -> Option<LockEvent> {
+        let header = message.header();
+        match (header.interface()?.as_str(), header.member()?.as_str()) {
+            (PORTAL_REQUEST, "Response") if header.path()?.as_str() == self.request.as_str() => {
+                let (response, results) = message
+                    .body()
+                    .deserialize::<(u32, HashMap<String, OwnedValue>)>()
+                    .ok()?;
+                if response == 0 {
+                    let session = results.get("session_handle")?.downcast_ref::<&str>().ok()?;
+                    self.session = Some(session.to_owned());
+                }
+                None
+            }
+            (PORTAL_INHIBIT, "StateChanged") => {
+                let (session, state) = message
+                    .body()
+                    .deserialize::<(OwnedObjectPath, HashMap<String, OwnedValue>)>()
+                    .ok()?;
+                if Some(session.as_str()) != self.session.as_deref() {
+                    return None;
+                }
+                let session_state = state.get("session-state")?.downcast_ref::<u32>().ok()?;
+                if session_state != PORTAL_SESSION_QUERY_END {
+                    return None;
+                }
+                let _ = self
+                    .connection
+                    .call_method(
+                        Some(PORTAL_SERVICE),
+                        PORTAL_PATH,
+                        Some(PORTAL_INHIBIT),
+                        "QueryEndResponse",
+                        &session,
+                    )
+                    .await;
+                Some(LockEvent::SessionEnding {
+                    source: PORTAL_INHIBIT.to_owned(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The services of the screen savers and of logind that were found, empty if the lock cannot be
+/// monitored.
+pub async fn is_lock_monitor_available() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let connection = Connection::session().await?;
+    let system = Connection::system().await.ok();
+    lock_monitor_available(&connection, system.as_ref()).await
+}
+
+async fn lock_monitor_available(
+    connection: &Connection,
+    system: Option<&Connection>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut found = Vec::new();
+    for monitor in screen_lock_monitors() {
+        match call_get_active(connection, &monitor).await {
+            Ok(_) => found.push(monitor.service.into_owned()),
+            // The bus answered, but the service is missing or does not implement the method
+            Err(zbus::Error::MethodError(..)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if let Some(system) = system {
+        if logind_session(system).await?.is_some() {
+            found.push(LOGIND_SERVICE.to_owned());
+        }
+    }
+    Ok(found)
+}
+
+/// Whether the screen is locked, according to the first screen saver that answers `GetActive`.
+pub async fn is_locked() -> Result<bool, Box<dyn std::error::Error>> {
+    let connection = Connection::session().await?;
+    get_active(&connection).await
+}
+
+async fn get_active(connection: &Connection) -> Result<bool, Box<dyn std::error::Error>> {
+    for monitor in screen_lock_monitors() {
+        match call_get_active(connection, &monitor).await {
+            Ok(reply) => return Ok(reply.body().deserialize()?),
+            Err(zbus::Error::MethodError(..)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err("No screen saver service found".into())
+}
+
+async fn call_get_active(connection: &Connection, monitor: &ScreenLock) -> zbus::Result<Message> {
+    connection
+        .call_method(
+            Some(monitor.service.as_ref()),
+            monitor.path.as_ref(),
+            Some(monitor.interface.as_ref()),
+            "GetActive",
+            &(),
+        )
+        .await
+}
+
+/// Report when the user has been idle for `threshold` and when they are active again. Uses
+/// Mutter's idle watches where available, otherwise polls the freedesktop screen saver or, outside
+/// of Wayland, the XScreenSaver extension.
+///
+/// If the monitor fails later on, the error is sent and the subscription stops.
+pub async fn on_idle(
+    threshold: Duration,
+    tx: tokio::sync::mpsc::Sender<Result<IdleEvent, String>>,
+) -> Result<IdleSubscription, Box<dyn std::error::Error>> {
     let connection = Connection::session().await?;
+    watch_idle(&connection, threshold, IDLE_POLL_INTERVAL, tx).await
+}
 
-    let proxy = zbus::fdo::DBusProxy::new(&connection).await?;
-    for monitor in SCREEN_LOCK_MONITORS.iter() {
-        let match_rule = MatchRule::builder()
-            .msg_type(zbus::MessageType::Signal)
-            .interface(monitor.interface.clone())?
-            .member("ActiveChanged")?
-            .build();
-        proxy.add_match_rule(match_rule).await?;
+async fn watch_idle(
+    connection: &Connection,
+    threshold: Duration,
+    poll_interval: Duration,
+    tx: tokio::sync::mpsc::Sender<Result<IdleEvent, String>>,
+) -> Result<IdleSubscription, Box<dyn std::error::Error>> {
+    if let Some(subscription) = mutter_idle_watch(connection, threshold, tx.clone()).await? {
+        return Ok(subscription);
     }
 
-    tokio::spawn(async move {
-        while let Ok(Some(_)) = zbus::MessageStream::from(&connection).try_next().await {
-            tx.send(()).await.unwrap();
+    let source = if screen_saver_idle_time(connection).await?.is_some() {
+        IdleTimeSource::ScreenSaver(connection.clone())
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        // XWayland only sees the input of X11 clients, so the user would seem idle
+        return Err("No idle monitor found for the Wayland session".into());
+    } else {
+        let x11 = XScreenSaver::connect().map_err(|e| format!("No idle monitor found: {e}"))?;
+        IdleTimeSource::X11(Box::new(x11))
+    };
+    Ok(poll_idle(source, threshold, poll_interval, tx))
+}
+
+/// Watch the idle time with Mutter's `IdleMonitor`. Returns `None` where Mutter is not running.
+async fn mutter_idle_watch(
+    connection: &Connection,
+    threshold: Duration,
+    tx: tokio::sync::mpsc::Sender<Result<IdleEvent, String>>,
+) -> Result<Option<IdleSubscription>, Box<dyn std::error::Error>> {
+    // Subscribe first, so that no watch fires unnoticed
+    let match_rule = MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .interface(MUTTER_IDLE_MONITOR)?
+        .member("WatchFired")?
+        .build();
+    let mut messages = MessageStream::for_match_rule(match_rule, connection, None).await?;
+
+    let interval_ms = u64::try_from(threshold.as_millis()).unwrap_or(u64::MAX);
+    let idle_watch: u32 = match mutter_call(connection, "AddIdleWatch", &interval_ms).await {
+        Ok(id) => id,
+        Err(zbus::Error::MethodError(..)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Mutter removes our watches once the connection is closed with the task
+    let connection = connection.clone();
+    let task = tokio::spawn(async move {
+        let mut active_watch = None;
+        loop {
+            let message = tokio::select! {
+                message = messages.try_next() => message,
+                _ = tx.closed() => break,
+            };
+            let message = match message {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    let _ = tx
+                        .send(Err("The idle monitor connection was closed".into()))
+                        .await;
+                    break;
+                }
+                Err(e) => {
+                    let _ = tx
+                        .send(Err(format!("Failed to watch the idle time: {e}")))
+                        .await;
+                    break;
+                }
+            };
+            let Ok(id) = message.body().deserialize::<u32>() else {
+                continue;
+            };
+            let event = if id == idle_watch {
+                // User active watches fire only once, so every idle period needs a new one
+                match mutter_call(&connection, "AddUserActiveWatch", &()).await {
+                    Ok(id) => active_watch = Some(id),
+                    Err(e) => {
+                        let _ = tx
+                            .send(Err(format!("Failed to watch for activity: {e}")))
+                            .await;
+                        break;
+                    }
+                }
+                IdleEvent::Idle
+            } else if Some(id) == active_watch {
+                active_watch = None;
+                IdleEvent::Active
+            } else {
+                continue;
+            };
+            if tx.send(Ok(event)).await.is_err() {
+                break;
+            }
         }
     });
 
-    Ok(())
+    Ok(Some(IdleSubscription { task }))
+}
+
+/// Call an `IdleMonitor` method that returns a watch id.
+async fn mutter_call<B>(connection: &Connection, method: &str, body: &B) -> zbus::Result<u32>
+where
+    B: zbus::export::serde::Serialize + zbus::zvariant::DynamicType,
+{
+    let reply = connection
+        .call_method(
+            Some(MUTTER_IDLE_MONITOR),
+            MUTTER_IDLE_MONITOR_PATH,
+            Some(MUTTER_IDLE_MONITOR),
+            method,
+            body,
+        )
+        .await?;
+    reply.body().deserialize()
 }
 
-pub async fn is_lock_monitor_available() -> bool {
-    let connection = Connection::session().await.unwrap();
-    for monitor in SCREEN_LOCK_MONITORS {
-        let res = connection.call_method(Some(monitor.interface.clone()), monitor.path.clone(), Some(monitor.interface.clone()), "GetActive", &()).await;
-        if res.is_ok() {
-            return true;
+/// `GetSessionIdleTime` of the freedesktop screen saver, or `None` where it is missing or does
+/// not support it, e.g. on GNOME.
+async fn screen_saver_idle_time(
+    connection: &Connection,
+) -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+    let reply = connection
+        .call_method(
+            Some(FREEDESKTOP_SCREEN_SAVER),
+            FREEDESKTOP_SCREEN_SAVER_PATH,
+            Some(FREEDESKTOP_SCREEN_SAVER),
+            "GetSessionIdleTime",
+            &(),
+        )
+        .await;
+    match reply {
+        Ok(reply) => Ok(Some(Duration::from_secs(
+            reply.body().deserialize::<u32>()?.into(),
+        ))),
+        Err(zbus::Error::MethodError(..)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+enum IdleTimeSource {
+    ScreenSaver(Connection),
+    X11(Box<XScreenSaver>),
+}
+
+impl IdleTimeSource {
+    async fn idle_time(&self) -> Result<Duration, Box<dyn std::error::Error>> {
+        match self {
+            IdleTimeSource::ScreenSaver(connection) => Ok(screen_saver_idle_time(connection)
+                .await?
+                .ok_or("The screen saver no longer reports the idle time")?),
+            IdleTimeSource::X11(x11) => x11.idle_time(),
         }
     }
-    false
+}
+
+/// Reads the idle time from the XScreenSaver extension.
+struct XScreenSaver {
+    conn: RustConnection,
+    root: Window,
+}
+
+impl XScreenSaver {
+    fn connect() -> Result<Self, Box<dyn std::error::Error>> {
+        let (conn, screen) = x11rb::connect(None)?;
+        // Fails if the server lacks the extension
+        conn.screensaver_query_version(1, 1)?.reply()?;
+        let root = conn.setup().roots[screen].root;
+        Ok(XScreenSaver { conn, root })
+    }
+
+    fn idle_time(&self) -> Result<Duration, Box<dyn std::error::Error>> {
+        let info = self.conn.screensaver_query_info(self.root)?.reply()?;
+        Ok(Duration::from_millis(info.ms_since_user_input.into()))
+    }
+}
+
+fn poll_idle(
+    source: IdleTimeSource,
+    threshold: Duration,
+    poll_interval: Duration,
+    tx: tokio::sync::mpsc::Sender<Result<IdleEvent, String>>,
+) -> IdleSubscription {
+    let task = tokio::spawn(async move {
+        let mut idle = false;
+        let mut ticks = tokio::time::interval(poll_interval);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = tx.closed() => break,
+            }
+            // The error is not `Send`, so it must not be held across the send
+            let idle_time = match source.idle_time().await.map_err(|e| e.to_string()) {
+                Ok(idle_time) => idle_time,
+                Err(e) => {
+                    let _ = tx
+                        .send(Err(format!("Failed to read the idle time: {e}")))
+                        .await;
+                    break;
+                }
+            };
+            let event = match (idle, idle_time >= threshold) {
+                (false, true) => IdleEvent::Idle,
+                (true, false) => IdleEvent::Active,
+                _ => continue,
+            };
+            idle = !idle;
+            if tx.send(Ok(event)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    IdleSubscription { task }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        os::unix::net::UnixStream,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use zbus::{
+        fdo::Properties,
+        names::InterfaceName,
+        zvariant::{ObjectPath, Value},
+        SignalContext,
+    };
+
+    use super::*;
+
+    const GNOME_PATH: &str = "/org/gnome/ScreenSaver";
+    const FREEDESKTOP_PATH: &str = "/org/freedesktop/ScreenSaver";
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/_32";
+    const OTHER_SESSION_PATH: &str = "/org/freedesktop/login1/session/_33";
+
+    #[derive(Default)]
+    struct MockScreenSaver {
+        active: bool,
+        idle_seconds: Arc<AtomicU32>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.ScreenSaver")]
+    impl MockScreenSaver {
+        fn get_active(&self) -> bool {
+            self.active
+        }
+
+        fn get_session_idle_time(&self) -> u32 {
+            self.idle_seconds.load(Ordering::SeqCst)
+        }
+
+        #[zbus(signal)]
+        async fn active_changed(ctxt: &SignalContext<'_>, active: bool) -> zbus::Result<()>;
+    }
+
+    struct MockGnomeScreenSaver;
+
+    #[zbus::interface(name = "org.gnome.ScreenSaver")]
+    impl MockGnomeScreenSaver {
+        #[zbus(signal)]
+        async fn active_changed(ctxt: &SignalContext<'_>, active: bool) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        async fn wake_up_screen(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+    }
+
+    const EXAMPLE_INTERFACE: &str = "org.example.ScreenSaver";
+    const EXAMPLE_PATH: &str = "/org/example/ScreenSaver";
+
+    struct MockExampleScreenSaver;
+
+    #[zbus::interface(name = "org.example.ScreenSaver")]
+    impl MockExampleScreenSaver {
+        fn get_active(&self) -> bool {
+            false
+        }
+
+        #[zbus(signal)]
+        async fn active_changed(ctxt: &SignalContext<'_>, active: bool) -> zbus::Result<()>;
+    }
+
+    const MUTTER_IDLE_WATCH: u32 = 1;
+    const MUTTER_USER_ACTIVE_WATCH: u32 = 2;
+
+    #[derive(Default)]
+    struct MockMutterIdleMonitor {
+        idle_watches: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[zbus::interface(name = "org.gnome.Mutter.IdleMonitor")]
+    impl MockMutterIdleMonitor {
+        fn add_idle_watch(&self, interval: u64) -> u32 {
+            self.idle_watches.lock().unwrap().push(interval);
+            MUTTER_IDLE_WATCH
+        }
+
+        fn add_user_active_watch(&self) -> u32 {
+            MUTTER_USER_ACTIVE_WATCH
+        }
+
+        #[zbus(signal)]
+        async fn watch_fired(ctxt: &SignalContext<'_>, id: u32) -> zbus::Result<()>;
+    }
+
+    async fn next_idle_event(
+        rx: &mut tokio::sync::mpsc::Receiver<Result<IdleEvent, String>>,
+    ) -> Result<IdleEvent, String> {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// The other ends of the inhibitor descriptors handed out, in order.
+    type Inhibitors = Arc<Mutex<Vec<UnixStream>>>;
+
+    struct MockLogindManager {
+        session: Option<&'static str>,
+        inhibitors: Inhibitors,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.login1.Manager")]
+    impl MockLogindManager {
+        fn get_session(&self, id: &str) -> zbus::fdo::Result<OwnedObjectPath> {
+            assert_eq!(id, "auto");
+            match self.session {
+                Some(session) => Ok(ObjectPath::try_from(session).unwrap().into()),
+                None => Err(zbus::fdo::Error::Failed(
+                    "Caller does not belong to any known session".to_owned(),
+                )),
+            }
+        }
+
+        fn inhibit(&self, what: &str, _who: &str, _why: &str, mode: &str) -> OwnedFd {
+            assert_eq!((what, mode), ("sleep", "delay"));
+            let (inhibitor, other) = UnixStream::pair().unwrap();
+            other.set_nonblocking(true).unwrap();
+            self.inhibitors.lock().unwrap().push(other);
+            std::os::fd::OwnedFd::from(inhibitor).into()
+        }
+
+        #[zbus(signal)]
+        async fn prepare_for_sleep(ctxt: &SignalContext<'_>, start: bool) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        async fn prepare_for_shutdown(ctxt: &SignalContext<'_>, start: bool) -> zbus::Result<()>;
+    }
+
+    struct MockLogindSession;
+
+    #[zbus::interface(name = "org.freedesktop.login1.Session")]
+    impl MockLogindSession {
+        #[zbus(signal)]
+        async fn lock(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        async fn unlock(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+    }
+
+    /// Emit a change of the `Active` property of a logind session.
+    async fn active_changed(ctxt: &SignalContext<'_>, active: bool) {
+        let active = Value::from(active);
+        let changed = HashMap::from([("Active", &active)]);
+        Properties::properties_changed(
+            ctxt,
+            InterfaceName::from_static_str(LOGIND_SESSION).unwrap(),
+            &changed,
+            &[],
+        )
+        .await
+        .unwrap();
+    }
+
+    const PORTAL_REQUEST_PATH: &str = "/org/freedesktop/portal/desktop/request/1_2/t";
+    const PORTAL_SESSION_PATH: &str = "/org/freedesktop/portal/desktop/session/1_2/t";
+
+    #[derive(Default)]
+    struct MockPortalInhibit {
+        query_end_responses: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.portal.Inhibit")]
+    impl MockPortalInhibit {
+        fn create_monitor(
+            &self,
+            _window: &str,
+            _options: HashMap<String, OwnedValue>,
+        ) -> OwnedObjectPath {
+            ObjectPath::try_from(PORTAL_REQUEST_PATH).unwrap().into()
+        }
+
+        fn query_end_response(&self, session_handle: ObjectPath<'_>) {
+            self.query_end_responses
+                .lock()
+                .unwrap()
+                .push(session_handle.to_string());
+        }
+
+        #[zbus(signal)]
+        async fn state_changed(
+            ctxt: &SignalContext<'_>,
+            session_handle: ObjectPath<'_>,
+            state: HashMap<&str, Value<'_>>,
+        ) -> zbus::Result<()>;
+    }
+
+    struct MockPortalRequest;
+
+    #[zbus::interface(name = "org.freedesktop.portal.Request")]
+    impl MockPortalRequest {
+        #[zbus(signal)]
+        async fn response(
+            ctxt: &SignalContext<'_>,
+            response: u32,
+            results: HashMap<&str, Value<'_>>,
+        ) -> zbus::Result<()>;
+    }
+
+    async fn portal_state_changed(ctxt: &SignalContext<'_>, session_state: u32) {
+        let state = HashMap::from([
+            ("screensaver-active", Value::from(false)),
+            ("session-state", Value::from(session_state)),
+        ]);
+        MockPortalInhibit::state_changed(
+            ctxt,
+            ObjectPath::try_from(PORTAL_SESSION_PATH).unwrap(),
+            state,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn mock_logind(session: Option<&'static str>) -> (Connection, Connection) {
+        mock_logind_with_inhibitors(session, Inhibitors::default()).await
+    }
+
+    async fn mock_logind_with_inhibitors(
+        session: Option<&'static str>,
+        inhibitors: Inhibitors,
+    ) -> (Connection, Connection) {
+        crate::dbus_mock::connect(|b| {
+            b.serve_at(
+                LOGIND_PATH,
+                MockLogindManager {
+                    session,
+                    inhibitors,
+                },
+            )?
+            .serve_at(SESSION_PATH, MockLogindSession)?
+            .serve_at(OTHER_SESSION_PATH, MockLogindSession)
+        })
+        .await
+    }
+
+    /// Whether the inhibitor at `index` is still open. Reading the other end only ends once it is
+    /// closed.
+    fn is_held(inhibitors: &Inhibitors, index: usize) -> bool {
+        let inhibitors = inhibitors.lock().unwrap();
+        let Some(mut other) = inhibitors.get(index) else {
+            return false;
+        };
+        matches!(other.read(&mut [0]), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+    }
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    async fn next_event(rx: &mut tokio::sync::mpsc::Receiver<LockEvent>) -> LockEvent {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn on_lock_reports_locked_and_unlocked() {
+        let (client, server) = crate::dbus_mock::connect(|b| {
+            b.serve_at(GNOME_PATH, MockGnomeScreenSaver)?
+                .serve_at(FREEDESKTOP_PATH, MockScreenSaver::default())
+        })
+        .await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, None, tx).await.unwrap();
+
+        let gnome = SignalContext::new(&server, GNOME_PATH).unwrap();
+        let freedesktop = SignalContext::new(&server, FREEDESKTOP_PATH).unwrap();
+        MockScreenSaver::active_changed(&freedesktop, true)
+            .await
+            .unwrap();
+        MockGnomeScreenSaver::wake_up_screen(&gnome).await.unwrap();
+        MockGnomeScreenSaver::active_changed(&gnome, false)
+            .await
+            .unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap();
+            events.push(event.unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![
+                LockEvent::Locked {
+                    source: "org.freedesktop.ScreenSaver".to_owned()
+                },
+                LockEvent::Unlocked {
+                    source: "org.gnome.ScreenSaver".to_owned()
+                },
+            ]
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn on_lock_ends_when_receiver_is_dropped() {
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (tx, rx) = tokio::sync::mpsc::channel(32);
+        let subscription = listen(&client, None, tx).await.unwrap();
+        drop(rx);
+
+        let freedesktop = SignalContext::new(&server, FREEDESKTOP_PATH).unwrap();
+        MockScreenSaver::active_changed(&freedesktop, true)
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), subscription.task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stopped_subscription_delivers_no_events() {
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let subscription = listen(&client, None, tx).await.unwrap();
+        subscription.stop();
+
+        let freedesktop = SignalContext::new(&server, FREEDESKTOP_PATH).unwrap();
+        MockScreenSaver::active_changed(&freedesktop, true)
+            .await
+            .unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        assert_eq!(event, None);
+        assert!(subscription.is_stopped());
+    }
+
+    #[tokio::test]
+    async fn on_lock_reports_logind_events_of_own_session() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (system, logind) = mock_logind(Some(SESSION_PATH)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, Some(&system), tx).await.unwrap();
+
+        let session = SignalContext::new(&logind, SESSION_PATH).unwrap();
+        let other_session = SignalContext::new(&logind, OTHER_SESSION_PATH).unwrap();
+        let manager = SignalContext::new(&logind, LOGIND_PATH).unwrap();
+        MockLogindSession::lock(&other_session).await.unwrap();
+        MockLogindSession::lock(&session).await.unwrap();
+        MockLogindManager::prepare_for_sleep(&manager, true)
+            .await
+            .unwrap();
+        MockLogindManager::prepare_for_sleep(&manager, false)
+            .await
+            .unwrap();
+        MockLogindSession::unlock(&session).await.unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..4 {
+            events.push(next_event(&mut rx).await);
+        }
+        assert_eq!(
+            events,
+            vec![
+                LockEvent::Locked {
+                    source: LOGIND_SESSION.to_owned()
+                },
+                LockEvent::Suspend {
+                    source: LOGIND_MANAGER.to_owned()
+                },
+                LockEvent::Resume {
+                    source: LOGIND_MANAGER.to_owned()
+                },
+                LockEvent::Unlocked {
+                    source: LOGIND_SESSION.to_owned()
+                },
+            ]
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn on_lock_merges_screen_saver_and_logind_events() {
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (system, logind) = mock_logind(Some(SESSION_PATH)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, Some(&system), tx).await.unwrap();
+
+        let freedesktop = SignalContext::new(&server, FREEDESKTOP_PATH).unwrap();
+        MockScreenSaver::active_changed(&freedesktop, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::Locked {
+                source: "org.freedesktop.ScreenSaver".to_owned()
+            }
+        );
+
+        let session = SignalContext::new(&logind, SESSION_PATH).unwrap();
+        MockLogindSession::unlock(&session).await.unwrap();
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::Unlocked {
+                source: LOGIND_SESSION.to_owned()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn on_lock_without_logind_session_uses_screen_savers() {
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (system, logind) = mock_logind(None).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, Some(&system), tx).await.unwrap();
+
+        let manager = SignalContext::new(&logind, LOGIND_PATH).unwrap();
+        MockLogindManager::prepare_for_sleep(&manager, true)
+            .await
+            .unwrap();
+        let freedesktop = SignalContext::new(&server, FREEDESKTOP_PATH).unwrap();
+        MockScreenSaver::active_changed(&freedesktop, true)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::Locked {
+                source: "org.freedesktop.ScreenSaver".to_owned()
+            }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn suspend_is_delayed_until_acknowledged() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let inhibitors = Inhibitors::default();
+        let (system, logind) =
+            mock_logind_with_inhibitors(Some(SESSION_PATH), inhibitors.clone()).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let subscription = listen(&client, Some(&system), tx).await.unwrap();
+        assert!(is_held(&inhibitors, 0));
+
+        let manager = SignalContext::new(&logind, LOGIND_PATH).unwrap();
+        MockLogindManager::prepare_for_sleep(&manager, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut rx).await,
+            LockEvent::Suspend { .. }
+        ));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(is_held(&inhibitors, 0));
+
+        subscription.acknowledge_suspend();
+        wait_until(|| !is_held(&inhibitors, 0)).await;
+
+        MockLogindManager::prepare_for_sleep(&manager, false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut rx).await,
+            LockEvent::Resume { .. }
+        ));
+        wait_until(|| is_held(&inhibitors, 1)).await;
+    }
+
+    #[tokio::test]
+    async fn suspend_is_released_after_timeout() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let inhibitors = Inhibitors::default();
+        let (system, logind) =
+            mock_logind_with_inhibitors(Some(SESSION_PATH), inhibitors.clone()).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let _subscription = listen(&client, Some(&system), tx).await.unwrap();
+
+        let manager = SignalContext::new(&logind, LOGIND_PATH).unwrap();
+        MockLogindManager::prepare_for_sleep(&manager, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut rx).await,
+            LockEvent::Suspend { .. }
+        ));
+
+        wait_until(|| !is_held(&inhibitors, 0)).await;
+    }
+
+    #[tokio::test]
+    async fn watch_idle_uses_mutter_idle_watches() {
+        let idle_watches = Arc::new(Mutex::new(Vec::new()));
+        let monitor = MockMutterIdleMonitor {
+            idle_watches: idle_watches.clone(),
+        };
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(MUTTER_IDLE_MONITOR_PATH, monitor)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        watch_idle(
+            &client,
+            Duration::from_secs(300),
+            Duration::from_millis(10),
+            tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(*idle_watches.lock().unwrap(), vec![300_000]);
+
+        let ctxt = SignalContext::new(&server, MUTTER_IDLE_MONITOR_PATH).unwrap();
+        MockMutterIdleMonitor::watch_fired(&ctxt, 7).await.unwrap();
+        MockMutterIdleMonitor::watch_fired(&ctxt, MUTTER_IDLE_WATCH)
+            .await
+            .unwrap();
+        assert_eq!(next_idle_event(&mut rx).await, Ok(IdleEvent::Idle));
+
+        MockMutterIdleMonitor::watch_fired(&ctxt, MUTTER_USER_ACTIVE_WATCH)
+            .await
+            .unwrap();
+        assert_eq!(next_idle_event(&mut rx).await, Ok(IdleEvent::Active));
+
+        // The user active watch was used up
+        MockMutterIdleMonitor::watch_fired(&ctxt, MUTTER_USER_ACTIVE_WATCH)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn watch_idle_polls_screen_saver_without_mutter() {
+        let idle_seconds = Arc::new(AtomicU32::new(0));
+        let screen_saver = MockScreenSaver {
+            idle_seconds: idle_seconds.clone(),
+            ..Default::default()
+        };
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, screen_saver)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        watch_idle(
+            &client,
+            Duration::from_secs(300),
+            Duration::from_millis(10),
+            tx,
+        )
+        .await
+        .unwrap();
+
+        idle_seconds.store(300, Ordering::SeqCst);
+        assert_eq!(next_idle_event(&mut rx).await, Ok(IdleEvent::Idle));
+        idle_seconds.store(0, Ordering::SeqCst);
+        assert_eq!(next_idle_event(&mut rx).await, Ok(IdleEvent::Active));
+    }
+
+    #[tokio::test]
+    async fn watch_idle_reports_failed_poll() {
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let subscription = watch_idle(
+            &client,
+            Duration::from_secs(300),
+            Duration::from_millis(10),
+            tx,
+        )
+        .await
+        .unwrap();
+
+        drop(server);
+        assert!(next_idle_event(&mut rx).await.is_err());
+        wait_until(|| subscription.is_stopped()).await;
+    }
+
+    #[tokio::test]
+    async fn watch_idle_reports_closed_mutter_connection() {
+        let monitor = MockMutterIdleMonitor {
+            idle_watches: Default::default(),
+        };
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(MUTTER_IDLE_MONITOR_PATH, monitor)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        watch_idle(
+            &client,
+            Duration::from_secs(300),
+            Duration::from_millis(10),
+            tx,
+        )
+        .await
+        .unwrap();
+
+        drop(server);
+        assert!(next_idle_event(&mut rx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn on_lock_reports_inactive_and_ending_logind_session() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+        let (system, logind) = mock_logind(Some(SESSION_PATH)).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, Some(&system), tx).await.unwrap();
+
+        let session = SignalContext::new(&logind, SESSION_PATH).unwrap();
+        let other_session = SignalContext::new(&logind, OTHER_SESSION_PATH).unwrap();
+        let manager = SignalContext::new(&logind, LOGIND_PATH).unwrap();
+        active_changed(&other_session, false).await;
+        active_changed(&session, false).await;
+        active_changed(&session, true).await;
+        MockLogindManager::prepare_for_shutdown(&manager, false)
+            .await
+            .unwrap();
+        MockLogindManager::prepare_for_shutdown(&manager, true)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::SessionInactive {
+                source: LOGIND_SESSION.to_owned()
+            }
+        );
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::SessionEnding {
+                source: LOGIND_MANAGER.to_owned()
+            }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn on_lock_answers_portal_query_end() {
+        let query_end_responses = Arc::new(Mutex::new(Vec::new()));
+        let inhibit = MockPortalInhibit {
+            query_end_responses: query_end_responses.clone(),
+        };
+        let (client, server) = crate::dbus_mock::connect(|b| {
+            b.serve_at(PORTAL_PATH, inhibit)?
+                .serve_at(PORTAL_REQUEST_PATH, MockPortalRequest)
+        })
+        .await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, None, tx).await.unwrap();
+
+        let request = SignalContext::new(&server, PORTAL_REQUEST_PATH).unwrap();
+        let results = HashMap::from([("session_handle", Value::from(PORTAL_SESSION_PATH))]);
+        MockPortalRequest::response(&request, 0, results)
+            .await
+            .unwrap();
+        let portal = SignalContext::new(&server, PORTAL_PATH).unwrap();
+        portal_state_changed(&portal, 1).await;
+        portal_state_changed(&portal, PORTAL_SESSION_QUERY_END).await;
+
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::SessionEnding {
+                source: PORTAL_INHIBIT.to_owned()
+            }
+        );
+        assert_eq!(
+            *query_end_responses.lock().unwrap(),
+            vec![PORTAL_SESSION_PATH.to_owned()]
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn lock_monitor_available_with_screen_saver() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at(FREEDESKTOP_PATH, MockScreenSaver::default()))
+                .await;
+
+        assert_eq!(
+            lock_monitor_available(&client, None).await.unwrap(),
+            vec!["org.freedesktop.ScreenSaver"]
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_monitor_available_with_kde_screen_saver() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at("/ScreenSaver", MockScreenSaver::default()))
+                .await;
+
+        assert_eq!(
+            lock_monitor_available(&client, None).await.unwrap(),
+            vec!["org.kde.screensaver"]
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_monitor_unavailable_without_screen_saver() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at("/", MockScreenSaver::default())).await;
+
+        assert!(lock_monitor_available(&client, None)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn lock_monitor_available_with_logind_session() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at("/", MockScreenSaver::default())).await;
+        let (system, _logind) = mock_logind(Some(SESSION_PATH)).await;
+
+        assert_eq!(
+            lock_monitor_available(&client, Some(&system))
+                .await
+                .unwrap(),
+            vec![LOGIND_SERVICE]
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_monitor_unavailable_without_logind_session() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at("/", MockScreenSaver::default())).await;
+        let (system, _logind) = mock_logind(None).await;
+
+        assert!(lock_monitor_available(&client, Some(&system))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn added_screen_lock_monitor_is_watched() {
+        add_screen_lock_monitor(EXAMPLE_INTERFACE, EXAMPLE_PATH).unwrap();
+        add_screen_lock_monitor(EXAMPLE_INTERFACE, EXAMPLE_PATH).unwrap();
+        let (client, server) =
+            crate::dbus_mock::connect(|b| b.serve_at(EXAMPLE_PATH, MockExampleScreenSaver)).await;
+
+        assert_eq!(
+            lock_monitor_available(&client, None).await.unwrap(),
+            vec![EXAMPLE_INTERFACE]
+        );
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        listen(&client, None, tx).await.unwrap();
+        let ctxt = SignalContext::new(&server, EXAMPLE_PATH).unwrap();
+        MockExampleScreenSaver::active_changed(&ctxt, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            next_event(&mut rx).await,
+            LockEvent::Locked {
+                source: EXAMPLE_INTERFACE.to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn add_screen_lock_monitor_rejects_invalid_names() {
+        assert!(add_screen_lock_monitor("not an interface", "/org/example").is_err());
+        assert!(add_screen_lock_monitor("org.example.Invalid", "not/a/path").is_err());
+    }
+
+    #[tokio::test]
+    async fn get_active_asks_first_available_screen_saver() {
+        let (client, _server) = crate::dbus_mock::connect(|b| {
+            b.serve_at(
+                FREEDESKTOP_PATH,
+                MockScreenSaver {
+                    active: true,
+                    ..Default::default()
+                },
+            )
+        })
+        .await;
+
+        assert!(get_active(&client).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn get_active_without_screen_saver() {
+        let (client, _server) =
+            crate::dbus_mock::connect(|b| b.serve_at("/", MockScreenSaver::default())).await;
+
+        assert!(get_active(&client).await.is_err());
+    }
 }
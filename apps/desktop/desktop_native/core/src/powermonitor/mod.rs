@@ -3,3 +3,111 @@
 #[cfg_attr(target_os = "macos", path = "unimplemented.rs")]
 mod powermonitor;
 pub use powermonitor::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockEvent {
+    /// `source` is the interface that reported the change, e.g. `org.gnome.ScreenSaver`.
+    Locked {
+        source: String,
+    },
+    Unlocked {
+        source: String,
+    },
+    /// The system is about to sleep, reported by `org.freedesktop.login1.Manager`. Sleep is
+    /// delayed until [LockSubscription::acknowledge_suspend] is called or a timeout passes.
+    Suspend {
+        source: String,
+    },
+    Resume {
+        source: String,
+    },
+    /// Another session became active, e.g. after switching users.
+    SessionInactive {
+        source: String,
+    },
+    /// The user is logging out or the system is shutting down.
+    SessionEnding {
+        source: String,
+    },
+}
+
+impl LockEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LockEvent::Locked { .. } => "locked",
+            LockEvent::Unlocked { .. } => "unlocked",
+            LockEvent::Suspend { .. } => "suspend",
+            LockEvent::Resume { .. } => "resume",
+            LockEvent::SessionInactive { .. } => "session-inactive",
+            LockEvent::SessionEnding { .. } => "session-ending",
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        match self {
+            LockEvent::Locked { source }
+            | LockEvent::Unlocked { source }
+            | LockEvent::Suspend { source }
+            | LockEvent::Resume { source }
+            | LockEvent::SessionInactive { source }
+            | LockEvent::SessionEnding { source } => source,
+        }
+    }
+}
+
+/// Returned by `on_lock`. Events are delivered until [LockSubscription::stop] is called or the
+/// receiver is dropped, dropping the subscription itself does not stop it.
+#[derive(Debug)]
+pub struct LockSubscription {
+    task: tokio::task::JoinHandle<()>,
+    suspend_acks: tokio::sync::mpsc::Sender<()>,
+}
+
+impl LockSubscription {
+    /// Let the system sleep after a [LockEvent::Suspend] has been handled.
+    pub fn acknowledge_suspend(&self) {
+        // A full channel already holds an acknowledgement
+        let _ = self.suspend_acks.try_send(());
+    }
+
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleEvent {
+    /// The user has been inactive for the threshold passed to `on_idle`.
+    Idle,
+    /// The user is active again after [IdleEvent::Idle].
+    Active,
+}
+
+impl IdleEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IdleEvent::Idle => "idle",
+            IdleEvent::Active => "active",
+        }
+    }
+}
+
+/// Returned by `on_idle`, stops like [LockSubscription].
+#[derive(Debug)]
+pub struct IdleSubscription {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl IdleSubscription {
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.task.is_finished()
+    }
+}
@@ -1,7 +1,28 @@
-pub async fn on_lock(_: tokio::sync::mpsc::Sender<()>) -> Result<(), Box<dyn std::error::Error>> {
-    unimplemented!();
+use std::time::Duration;
+
+use super::{IdleEvent, IdleSubscription, LockEvent, LockSubscription};
+
+pub async fn on_lock(
+    _: tokio::sync::mpsc::Sender<LockEvent>,
+) -> Result<LockSubscription, Box<dyn std::error::Error>> {
+    Err("The lock monitor is not available on this platform".into())
+}
+
+pub fn add_screen_lock_monitor(_: &str, _: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Err("Screen lock monitors are not available on this platform".into())
+}
+
+pub async fn is_lock_monitor_available() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    Ok(Vec::new())
+}
+
+pub async fn is_locked() -> Result<bool, Box<dyn std::error::Error>> {
+    Err("The lock state is not available on this platform".into())
 }
 
-pub async fn is_lock_monitor_available() -> bool {
-    return false;
+pub async fn on_idle(
+    _: Duration,
+    _: tokio::sync::mpsc::Sender<Result<IdleEvent, String>>,
+) -> Result<IdleSubscription, Box<dyn std::error::Error>> {
+    Err("The idle monitor is not available on this platform".into())
 }
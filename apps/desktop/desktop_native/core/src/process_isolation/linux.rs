@@ -1,5 +1,7 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use libc::{c_int, self};
+
+use super::{CoreLimit, IsolationStatus};
 #[cfg(target_env = "gnu")]
 use libc::c_uint;
 
@@ -14,6 +16,8 @@ const RLIMIT_CORE: c_uint = 4;
 // or attach a debugger to it.
 // https://github.com/torvalds/linux/blob/a38297e3fb012ddfa7ce0321a7e5a8daeb1872b6/include/uapi/linux/prctl.h#L14
 const PR_SET_DUMPABLE: c_int = 4;
+// https://github.com/torvalds/linux/blob/a38297e3fb012ddfa7ce0321a7e5a8daeb1872b6/include/uapi/linux/prctl.h#L13
+const PR_GET_DUMPABLE: c_int = 3;
 
 pub fn disable_coredumps() -> Result<()> {
     let rlimit = libc::rlimit {
@@ -49,3 +53,149 @@ pub fn disable_memory_access() -> Result<()> {
 
     Ok(())
 }
+
+/// The isolation status of this process, or of the process `pid` of the same user, e.g. a
+/// renderer process.
+pub fn status(pid: Option<u32>) -> Result<IsolationStatus> {
+    let proc_status = match pid {
+        Some(pid) => std::fs::read_to_string(format!("/proc/{}/status", pid))?,
+        None => std::fs::read_to_string("/proc/self/status")?,
+    };
+
+    let dumpable = match pid {
+        Some(pid) => is_dumpable(pid, &proc_status)?,
+        None => {
+            let dumpable = unsafe { libc::prctl(PR_GET_DUMPABLE) };
+            if dumpable < 0 {
+                let e = std::io::Error::last_os_error();
+                return Err(anyhow!("failed to get dumpable flag {}", e));
+            }
+            dumpable != 0
+        }
+    };
+
+    let mut rlimit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    let result = match pid {
+        Some(pid) => unsafe {
+            libc::prlimit(
+                pid as libc::pid_t,
+                RLIMIT_CORE,
+                std::ptr::null(),
+                &mut rlimit,
+            )
+        },
+        None => unsafe { libc::getrlimit(RLIMIT_CORE, &mut rlimit) },
+    };
+    if result != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(anyhow!("failed to get core dump limit {}", e));
+    }
+
+    // Yama is optional, without it only the dumpable flag restricts ptrace
+    let ptrace_scope = match std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope") {
+        Ok(scope) => Some(scope.trim().parse()?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let core_pattern = std::fs::read_to_string("/proc/sys/kernel/core_pattern")?
+        .trim_end()
+        .to_owned();
+    let tracer_pid = parse_tracer_pid(&proc_status)?;
+
+    Ok(IsolationStatus {
+        dumpable,
+        core_limit: CoreLimit {
+            soft: limit(rlimit.rlim_cur),
+            hard: limit(rlimit.rlim_max),
+        },
+        ptrace_scope,
+        core_pattern,
+        tracer_pid,
+    })
+}
+
+// The dumpable flag can only be read by the process itself. The kernel hands `/proc/<pid>` to root
+// once a process is no longer dumpable, which tells the two apart unless the process runs as root,
+// in which case it is reported as dumpable.
+fn is_dumpable(pid: u32, proc_status: &str) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = std::fs::metadata(format!("/proc/{}", pid))?.uid();
+    Ok(owner != 0 || parse_effective_uid(proc_status)? == 0)
+}
+
+fn limit(value: libc::rlim_t) -> Option<u64> {
+    (value != libc::RLIM_INFINITY).then_some(value)
+}
+
+/// Reads the `TracerPid` field of `/proc/<pid>/status`, which is 0 without a tracer.
+fn parse_tracer_pid(status: &str) -> Result<Option<u32>> {
+    let pid: u32 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))
+        .ok_or(anyhow!("missing TracerPid in process status"))?
+        .trim()
+        .parse()?;
+    Ok((pid != 0).then_some(pid))
+}
+
+/// Reads the effective user id from the `Uid` field of `/proc/<pid>/status`.
+fn parse_effective_uid(status: &str) -> Result<u32> {
+    Ok(status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().nth(1))
+        .ok_or(anyhow!("missing Uid in process status"))?
+        .parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tracer_pid() {
+        let status = "Name:\tbitwarden\nState:\tS (sleeping)\nTracerPid:\t1234\nUid:\t1000\n";
+        assert_eq!(parse_tracer_pid(status).unwrap(), Some(1234));
+
+        let status = "Name:\tbitwarden\nTracerPid:\t0\n";
+        assert_eq!(parse_tracer_pid(status).unwrap(), None);
+
+        assert!(parse_tracer_pid("Name:\tbitwarden\n").is_err());
+    }
+
+    #[test]
+    fn parses_effective_uid() {
+        let status = "Name:\tbitwarden\nUid:\t1000\t1001\t1000\t1000\n";
+        assert_eq!(parse_effective_uid(status).unwrap(), 1001);
+
+        assert!(parse_effective_uid("Name:\tbitwarden\n").is_err());
+    }
+
+    #[test]
+    fn status_matches_core_dump_limit() {
+        let status = status(None).unwrap();
+        assert_eq!(
+            status.core_limit.is_disabled(),
+            is_core_dumping_disabled().unwrap()
+        );
+    }
+
+    #[test]
+    fn status_of_other_process() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let status = status(Some(child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let status = status.unwrap();
+        assert!(status.dumpable);
+        assert_eq!(status.tracer_pid, None);
+    }
+}
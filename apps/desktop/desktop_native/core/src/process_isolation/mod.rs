@@ -3,3 +3,39 @@
 #[cfg_attr(target_os = "macos", path = "macos.rs")]
 mod process_isolation;
 pub use process_isolation::*;
+
+/// How well the process is protected from other processes reading its memory, see [status].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IsolationStatus {
+    /// Whether the process is still dumpable, i.e. [disable_memory_access] has not taken effect.
+    pub dumpable: bool,
+    pub core_limit: CoreLimit,
+    /// The Yama ptrace scope, `None` if Yama is not enabled.
+    pub ptrace_scope: Option<u32>,
+    /// `/proc/sys/kernel/core_pattern`, the file core dumps are written to or the handler they are
+    /// piped to.
+    pub core_pattern: String,
+    /// The process tracing this one, e.g. a debugger.
+    pub tracer_pid: Option<u32>,
+}
+
+impl IsolationStatus {
+    /// Whether core dumps are piped to a handler like systemd-coredump, which may store them
+    /// outside of the user's control.
+    pub fn is_core_pattern_piped(&self) -> bool {
+        self.core_pattern.starts_with('|')
+    }
+}
+
+/// The soft and hard `RLIMIT_CORE` in bytes, `None` if unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoreLimit {
+    pub soft: Option<u64>,
+    pub hard: Option<u64>,
+}
+
+impl CoreLimit {
+    pub fn is_disabled(&self) -> bool {
+        self.soft == Some(0) && self.hard == Some(0)
+    }
+}
@@ -1,5 +1,7 @@
 use anyhow::{bail, Result};
 
+use super::IsolationStatus;
+
 pub fn disable_coredumps() -> Result<()> {
     bail!("Not implemented on Windows")
 }
@@ -11,3 +13,7 @@ pub fn is_core_dumping_disabled() -> Result<bool> {
 pub fn disable_memory_access() -> Result<()> {
     bail!("Not implemented on Windows")
 }
+
+pub fn status(_pid: Option<u32>) -> Result<IsolationStatus> {
+    bail!("Not implemented on Windows")
+}
@@ -0,0 +1,316 @@
+//! Buffers for decrypted secrets that are kept out of swap and core dumps.
+//!
+//! On unix a [SecretBuf] gets its own mapping, surrounded by inaccessible guard pages so overflows
+//! fault instead of reading neighbouring memory. The pages are locked into memory and on Linux
+//! excluded from core dumps and zeroed in forked children. Locking is best effort, as it is limited
+//! by `RLIMIT_MEMLOCK`. On other platforms the secret lives on the heap. The contents are zeroed on
+//! drop everywhere.
+
+use std::{fmt, ops::Deref, str::Utf8Error};
+
+use crate::error::{Error, Result};
+
+/// A fixed size buffer for secret bytes, zeroed on drop.
+pub struct SecretBuf {
+    region: sys::Region,
+    len: usize,
+}
+
+// The buffer is exclusively owned, like a `Box<[u8]>`
+unsafe impl Send for SecretBuf {}
+unsafe impl Sync for SecretBuf {}
+
+impl SecretBuf {
+    /// Allocate a zeroed buffer of `len` bytes.
+    pub fn new(len: usize) -> Result<Self> {
+        let region = sys::Region::alloc(len).map_err(Error::SecretAlloc)?;
+        Ok(SecretBuf { region, len })
+    }
+
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        let mut buf = SecretBuf::new(data.len())?;
+        buf.as_mut_bytes().copy_from_slice(data);
+        Ok(buf)
+    }
+
+    /// Move `data` into a secret buffer, zeroing the original allocation.
+    pub fn from_vec(mut data: Vec<u8>) -> Result<Self> {
+        let buf = SecretBuf::from_slice(&data);
+        zeroize(&mut data);
+        buf
+    }
+
+    /// Move `data` into a secret buffer, zeroing the original allocation.
+    pub fn from_string(data: String) -> Result<Self> {
+        SecretBuf::from_vec(data.into_bytes())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.region.ptr().as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.region.ptr().as_ptr(), self.len) }
+    }
+
+    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(self.as_bytes())
+    }
+
+    /// Shorten the buffer to `len` bytes, zeroing the rest. Has no effect if `len` is greater than
+    /// the current length.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            zeroize(&mut self.as_mut_bytes()[len..]);
+            self.len = len;
+        }
+    }
+}
+
+impl Drop for SecretBuf {
+    fn drop(&mut self) {
+        // Zero the whole allocation, truncated bytes were already zeroed but cost nothing to redo
+        let capacity = self.region.capacity();
+        zeroize(unsafe { std::slice::from_raw_parts_mut(self.region.ptr().as_ptr(), capacity) });
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for SecretBuf {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl fmt::Debug for SecretBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBuf([REDACTED; {}])", self.len)
+    }
+}
+
+impl PartialEq<[u8]> for SecretBuf {
+    /// Compares in constant time for inputs of equal length.
+    fn eq(&self, other: &[u8]) -> bool {
+        self.len == other.len()
+            && self
+                .as_bytes()
+                .iter()
+                .zip(other)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl PartialEq<str> for SecretBuf {
+    fn eq(&self, other: &str) -> bool {
+        *self == *other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for SecretBuf {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+/// Overwrite `data` with zeros in a way the compiler cannot elide.
+fn zeroize(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{io, ptr::NonNull};
+
+    /// Data pages between two guard pages: `[guard][data..][guard]`.
+    pub struct Region {
+        base: NonNull<u8>,
+        page_size: usize,
+        data_pages: usize,
+    }
+
+    impl Region {
+        pub fn alloc(len: usize) -> io::Result<Self> {
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            let data_pages = len.div_ceil(page_size).max(1);
+            let size = (data_pages + 2) * page_size;
+
+            let base = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    size,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            if base == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let region = Region {
+                base: NonNull::new(base as *mut u8).unwrap(),
+                page_size,
+                data_pages,
+            };
+
+            let guards = [region.base.as_ptr(), unsafe {
+                region.ptr().as_ptr().add(region.capacity())
+            }];
+            for guard in guards {
+                if unsafe { libc::mprotect(guard as *mut _, page_size, libc::PROT_NONE) } != 0 {
+                    // Unmapped when the region is dropped
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            let data = region.ptr().as_ptr() as *mut libc::c_void;
+            unsafe {
+                // Fails once RLIMIT_MEMLOCK is exhausted, the buffer is still usable
+                libc::mlock(data, region.capacity());
+                #[cfg(target_os = "linux")]
+                {
+                    // Not supported by older kernels
+                    libc::madvise(data, region.capacity(), libc::MADV_DONTDUMP);
+                    libc::madvise(data, region.capacity(), libc::MADV_WIPEONFORK);
+                }
+            }
+            Ok(region)
+        }
+
+        pub fn ptr(&self) -> NonNull<u8> {
+            unsafe { NonNull::new_unchecked(self.base.as_ptr().add(self.page_size)) }
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.data_pages * self.page_size
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munlock(self.ptr().as_ptr() as *const _, self.capacity());
+                libc::munmap(
+                    self.base.as_ptr() as *mut _,
+                    (self.data_pages + 2) * self.page_size,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::{io, ptr::NonNull};
+
+    pub struct Region {
+        data: NonNull<[u8]>,
+    }
+
+    impl Region {
+        pub fn alloc(len: usize) -> io::Result<Self> {
+            let data = Box::into_raw(vec![0u8; len].into_boxed_slice());
+            Ok(Region {
+                data: NonNull::new(data).unwrap(),
+            })
+        }
+
+        pub fn ptr(&self) -> NonNull<u8> {
+            self.data.cast()
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            drop(unsafe { Box::from_raw(self.data.as_ptr()) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_slice_copies_data() {
+        let secret = SecretBuf::from_slice(b"secret").unwrap();
+        assert_eq!(secret.as_bytes(), b"secret");
+        assert_eq!(secret.as_str().unwrap(), "secret");
+        assert_eq!(secret, "secret");
+        assert_ne!(secret, "secreT");
+        assert_ne!(secret, "secrets");
+    }
+
+    #[test]
+    fn from_vec_takes_data() {
+        let secret = SecretBuf::from_vec(b"secret".to_vec()).unwrap();
+        assert_eq!(secret, "secret");
+        assert!(SecretBuf::from_vec(Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncate_zeroes_tail() {
+        let mut secret = SecretBuf::from_slice(b"secret").unwrap();
+        secret.truncate(3);
+        assert_eq!(secret, "sec");
+        assert_eq!(secret.len(), 3);
+        let tail = unsafe { std::slice::from_raw_parts(secret.as_ptr().add(3), 3) };
+        assert_eq!(tail, [0, 0, 0]);
+
+        secret.truncate(10);
+        assert_eq!(secret.len(), 3);
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let secret = SecretBuf::from_slice(b"secret").unwrap();
+        assert_eq!(format!("{:?}", secret), "SecretBuf([REDACTED; 6])");
+    }
+
+    #[test]
+    fn spans_multiple_pages() {
+        let data = vec![0xa5u8; 3 * 4096 + 1];
+        let secret = SecretBuf::from_slice(&data).unwrap();
+        assert_eq!(secret.as_bytes(), data.as_slice());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pages_are_excluded_from_dumps() {
+        let secret = SecretBuf::from_slice(b"secret").unwrap();
+        let address = format!("{:x}-", secret.as_ptr() as usize);
+
+        // The data pages are a separate mapping from their guard pages, starting at the buffer
+        let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let flags = smaps
+            .lines()
+            .skip_while(|line| !line.starts_with(&address))
+            .find(|line| line.starts_with("VmFlags:"))
+            .unwrap();
+        assert!(flags.contains(" dd"), "{}", flags);
+        assert!(flags.contains(" wf"), "{}", flags);
+    }
+}
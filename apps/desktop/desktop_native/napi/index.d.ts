@@ -16,9 +16,23 @@ export namespace passwords {
 }
 export namespace biometrics {
   export function prompt(hwnd: Buffer, message: string): Promise<boolean>
+  /** Cancel a pending biometric prompt. The pending prompt resolves to `false`. */
+  export function cancelPrompt(): Promise<void>
+  /**
+   * Subscribe to the progress of biometric prompts, e.g. `retry-scan` when a fingerprint
+   * reader needs another swipe. Replaces any previous subscription.
+   */
+  export function onVerifyStatus(callback: (err: Error | null, arg: VerifyProgress) => any): Promise<void>
   export function available(): Promise<boolean>
   export function setBiometricSecret(service: string, account: string, secret: string, keyMaterial: KeyMaterial | undefined | null, ivB64: string): Promise<string>
   export function getBiometricSecret(service: string, account: string, keyMaterial?: KeyMaterial | undefined | null): Promise<string>
+  export function hasBiometricSecret(service: string, account: string): Promise<boolean>
+  export function deleteBiometricSecret(service: string, account: string): Promise<void>
+  /**
+   * Re-encrypts the stored secret with a key derived from a new OS key part. The returned iv
+   * must be used to derive the key material for future calls.
+   */
+  export function rotateBiometricSecret(service: string, account: string, keyMaterial?: KeyMaterial | undefined | null): Promise<OsDerivedKey>
   /**
    * Derives key material from biometric data. Returns a string encoded with a
    * base64 encoded key and the base64 encoded challenge used to create it
@@ -29,25 +43,198 @@ export namespace biometrics {
    * `format!("<key_base64>|<iv_base64>")`
    */
   export function deriveKeyMaterial(iv?: string | undefined | null): Promise<OsDerivedKey>
+  /**
+   * Seal the OS key part to the TPM, optionally bound to the PCR selection `pcrs`, e.g.
+   * `sha256:0,7`. Only supported on Linux.
+   */
+  export function enableTpmSealing(pcrs?: string | undefined | null): Promise<void>
+  /** Stop sealing the OS key part and remove the sealed key part. */
+  export function disableTpmSealing(): Promise<void>
+  export function isTpmAvailable(): Promise<boolean>
+  /**
+   * Wrap the OS key part with the key `key_id` on the PKCS#11 token with `token_serial`, using
+   * the PKCS#11 `module`. Takes precedence over TPM sealing. Only supported on Linux.
+   */
+  export function enablePkcs11(module: string, tokenSerial: string, keyId: Buffer): Promise<void>
+  export function disablePkcs11(): Promise<void>
+  /**
+   * Log in to the configured PKCS#11 token before deriving the key material. Pass no PIN for
+   * readers with a PIN pad.
+   */
+  export function pkcs11Login(pin?: string | undefined | null): Promise<void>
+  export function listPkcs11Tokens(module: string): Promise<Array<Pkcs11Token>>
+  /** List the RSA and EC keys on the token, no login is required. */
+  export function listPkcs11Keys(module: string, tokenSerial: string): Promise<Array<Pkcs11Key>>
+  /**
+   * Cache keys derived by `getBiometricSecret` in the session keyring for `timeout_seconds`,
+   * so later calls can omit the key material. `null` disables the cache and revokes all cached
+   * keys. Only supported on Linux.
+   */
+  export function setKeyCacheTimeout(timeoutSeconds?: number | undefined | null): Promise<void>
+  /** Check if a cached key for the biometric secret exists, in which case the prompt can be skipped. */
+  export function hasCachedKey(service: string, account: string): Promise<boolean>
+  /** Revoke all cached keys. Should be called when the vault is locked. */
+  export function revokeCachedKeys(): Promise<void>
   export interface KeyMaterial {
     osKeyPartB64: string
     clientKeyPartB64?: string
   }
+  export interface Pkcs11Token {
+    label: string
+    manufacturer: string
+    model: string
+    serial: string
+    /** The reader has a PIN pad, call `pkcs11Login` without a PIN. */
+    protectedAuthenticationPath: boolean
+  }
+  export interface Pkcs11Key {
+    id: Buffer
+    label: string
+    /** `rsa` or `ec` */
+    keyType: string
+  }
+  export interface VerifyProgress {
+    /** e.g. `waiting` or `retry-scan` */
+    status: string
+    /** The message passed to `prompt`, to show while the reader waits for a finger. */
+    message: string
+  }
   export interface OsDerivedKey {
     keyB64: string
     ivB64: string
   }
 }
+export namespace pins {
+  /**
+   * Protect the base64 encoded user key with a key derived from the PIN. The protected key is
+   * deleted after `max_attempts` failed unlocks.
+   */
+  export function setPin(service: string, account: string, pin: string, email: string, kdf: KdfConfig, userKeyB64: string, maxAttempts: number): Promise<void>
+  /** Decrypt the user key protected with the PIN, returning it base64 encoded. */
+  export function unlockWithPin(service: string, account: string, pin: string, email: string, kdf: KdfConfig): Promise<string>
+  export function hasPin(service: string, account: string): Promise<boolean>
+  /** Number of unlock attempts left before the protected key is deleted. */
+  export function remainingPinAttempts(service: string, account: string): Promise<number>
+  export function removePin(service: string, account: string): Promise<void>
+  /**
+   * The account KDF settings. `kdf_type` is 0 for PBKDF2 and 1 for Argon2id, `memory` is in
+   * MiB.
+   */
+  export interface KdfConfig {
+    kdfType: number
+    iterations: number
+    memory?: number
+    parallelism?: number
+  }
+}
 export namespace clipboards {
-  export function read(): Promise<string>
-  export function write(text: string, password: boolean): Promise<void>
+  /**
+   * `target` is one of `clipboard` (the default), `primary` for the selection pasted with
+   * middle-click on Linux, or `both`.
+   */
+  export function read(target?: string | undefined | null): Promise<string>
+  /**
+   * Write to the clipboard. With `clear_after_ms`, the clipboard is cleared after the timeout
+   * unless the user copied something else, and the id of the pending clear is returned.
+   */
+  export function write(text: string, password: boolean, clearAfterMs?: number | undefined | null, target?: string | undefined | null): Promise<number | null>
+  export function clear(target?: string | undefined | null): Promise<void>
+  /** Cancel a pending clear. Returns `false` if it already ran. */
+  export function cancelClear(id: number): Promise<boolean>
+  /** One of `pending`, `cleared`, `replaced`, `cancelled` or `failed`. */
+  export function clearStatus(id: number): Promise<string>
+  /**
+   * Offer `text` on the clipboard until it has been pasted once, then clear the clipboard.
+   * The callback receives `pasted`, `replaced`, `cancelled` or `failed` when the offer ends.
+   * Only supported on Linux.
+   */
+  export function writePasteOnce(text: string, callback: (err: Error | null, arg: string) => any): Promise<void>
+  /** Withdraw the pending paste-once offer. Returns `false` if there was none. */
+  export function cancelPasteOnce(): Promise<boolean>
+  /**
+   * Call `callback` whenever the clipboard of `target` changes, until the returned
+   * subscription is stopped. With `content`, the new text is included in the event.
+   */
+  export function watch(callback: (err: Error | null, arg: ClipboardChange) => any, target: string | undefined | null, content: boolean): Promise<ClipboardSubscription>
+  export interface ClipboardChange {
+    /** `clipboard` or `primary` */
+    target: string
+    text?: string
+  }
+  export class ClipboardSubscription {
+    /** Stop listening. Calling it again has no effect. */
+    stop(): void
+    get stopped(): boolean
+  }
 }
 export namespace processisolations {
   export function disableCoredumps(): Promise<void>
   export function isCoreDumpingDisabled(): Promise<boolean>
   export function disableMemoryAccess(): Promise<void>
+  /**
+   * Report whether the process memory can be read by other processes, e.g. because a debugger
+   * is attached. Reports on the process `pid` of the same user if given, e.g. a renderer.
+   */
+  export function status(pid?: number | undefined | null): Promise<IsolationStatus>
+  export interface IsolationStatus {
+    /** False once `disableMemoryAccess` has taken effect. */
+    dumpable: boolean
+    /** The soft core dump size limit in bytes, unset if unlimited. */
+    coreLimitSoft?: number
+    /** The hard core dump size limit in bytes, unset if unlimited. */
+    coreLimitHard?: number
+    /** The Yama ptrace scope, unset if Yama is not enabled. */
+    ptraceScope?: number
+    corePattern: string
+    /** Core dumps are piped to a handler like systemd-coredump. */
+    corePatternPiped: boolean
+    /** The process tracing this one, e.g. a debugger. */
+    tracerPid?: number
+  }
 }
 export namespace powermonitors {
-  export function onLock(callback: (err: Error | null, ) => any): Promise<void>
-  export function isLockMonitorAvailable(): Promise<boolean>
+  /**
+   * Call `callback` whenever the screen is locked or unlocked, the system suspends or resumes
+   * or the session becomes inactive or ends, until the returned subscription is stopped.
+   */
+  export function onLock(callback: (err: Error | null, arg: LockEvent) => any): Promise<LockSubscription>
+  /**
+   * Call `callback` when the user has been idle for `threshold_seconds` and when they are
+   * active again, until the returned subscription is stopped. If the monitor fails, the
+   * callback receives the error and the subscription stops.
+   */
+  export function onIdle(thresholdSeconds: number, callback: (err: Error | null, arg: IdleEvent) => any): Promise<IdleSubscription>
+  /**
+   * Also watch the screen saver `interface` at `path`, served under the name of the
+   * interface. Applies to subscriptions and checks started afterwards.
+   */
+  export function addScreenLockMonitor(interface: string, path: string): void
+  /** The D-Bus services that report the lock, empty if it cannot be monitored. */
+  export function isLockMonitorAvailable(): Promise<Array<string>>
+  export function isLocked(): Promise<boolean>
+  export interface LockEvent {
+    /** `locked`, `unlocked`, `suspend`, `resume`, `session-inactive` or `session-ending` */
+    kind: string
+    /** The D-Bus interface that reported the change, e.g. `org.gnome.ScreenSaver`. */
+    source: string
+  }
+  export class LockSubscription {
+    /** Stop listening. Calling it again has no effect. */
+    stop(): void
+    get stopped(): boolean
+    /**
+     * Let the system sleep once a `suspend` event has been handled. Sleep is delayed for a
+     * few seconds at most otherwise.
+     */
+    acknowledgeSuspend(): void
+  }
+  export interface IdleEvent {
+    /** `idle` or `active` */
+    kind: string
+  }
+  export class IdleSubscription {
+    /** Stop listening. Calling it again has no effect. */
+    stop(): void
+    get stopped(): boolean
+  }
 }
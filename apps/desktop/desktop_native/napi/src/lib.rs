@@ -5,7 +5,9 @@ pub mod passwords {
     /// Fetch the stored password from the keychain.
     #[napi]
     pub async fn get_password(service: String, account: String) -> napi::Result<String> {
+        // The string handed to JS can no longer be kept in secret memory
         desktop_core::password::get_password(&service, &account)
+            .and_then(|password| Ok(password.as_str()?.to_owned()))
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
@@ -13,6 +15,7 @@ pub mod passwords {
     #[napi]
     pub async fn get_password_keytar(service: String, account: String) -> napi::Result<String> {
         desktop_core::password::get_password_keytar(&service, &account)
+            .and_then(|password| Ok(password.as_str()?.to_owned()))
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
@@ -44,6 +47,12 @@ pub mod passwords {
 #[napi]
 pub mod biometrics {
     use desktop_core::biometric::{Biometric, BiometricTrait};
+    use napi::{
+        threadsafe_function::{
+            ErrorStrategy::CalleeHandled, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+        },
+        tokio,
+    };
 
     // Prompt for biometric confirmation
     #[napi]
@@ -54,6 +63,34 @@ pub mod biometrics {
         Biometric::prompt(hwnd.into(), message).await.map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
+    /// Cancel a pending biometric prompt. The pending prompt resolves to `false`.
+    #[napi]
+    pub async fn cancel_prompt() -> napi::Result<()> {
+        Biometric::cancel_prompt().await.map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Subscribe to the progress of biometric prompts, e.g. `retry-scan` when a fingerprint
+    /// reader needs another swipe. Replaces any previous subscription.
+    #[napi]
+    pub async fn on_verify_status(
+        callback: ThreadsafeFunction<VerifyProgress, CalleeHandled>,
+    ) -> napi::Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        desktop_core::biometric::on_verify_status(tx);
+        tokio::spawn(async move {
+            while let Some(progress) = rx.recv().await {
+                callback.call(
+                    Ok(VerifyProgress {
+                        status: progress.status.as_str().to_owned(),
+                        message: progress.message,
+                    }),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        });
+        Ok(())
+    }
+
     #[napi]
     pub async fn available() -> napi::Result<bool> {
         Biometric::available().await.map_err(|e| napi::Error::from_reason(e.to_string()))
@@ -83,10 +120,34 @@ pub mod biometrics {
         account: String,
         key_material: Option<KeyMaterial>,
     ) -> napi::Result<String> {
-        let result =
-            Biometric::get_biometric_secret(&service, &account, key_material.map(|m| m.into()))
-                .map_err(|e| napi::Error::from_reason(e.to_string()));
-        result
+        Biometric::get_biometric_secret(&service, &account, key_material.map(|m| m.into()))
+            .and_then(|secret| Ok(secret.as_str()?.to_owned()))
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub async fn has_biometric_secret(service: String, account: String) -> napi::Result<bool> {
+        Biometric::has_biometric_secret(&service, &account)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub async fn delete_biometric_secret(service: String, account: String) -> napi::Result<()> {
+        Biometric::delete_biometric_secret(&service, &account)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Re-encrypts the stored secret with a key derived from a new OS key part. The returned iv
+    /// must be used to derive the key material for future calls.
+    #[napi]
+    pub async fn rotate_biometric_secret(
+        service: String,
+        account: String,
+        key_material: Option<KeyMaterial>,
+    ) -> napi::Result<OsDerivedKey> {
+        Biometric::rotate_biometric_secret(&service, &account, key_material.map(|m| m.into()))
+            .map(|k| k.into())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
     /// Derives key material from biometric data. Returns a string encoded with a
@@ -103,6 +164,161 @@ pub mod biometrics {
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
+    /// Seal the OS key part to the TPM, optionally bound to the PCR selection `pcrs`, e.g.
+    /// `sha256:0,7`. Only supported on Linux.
+    #[napi]
+    pub async fn enable_tpm_sealing(pcrs: Option<String>) -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            desktop_core::biometric::tpm::enable(desktop_core::biometric::tpm::TpmConfig { pcrs })
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = pcrs;
+            Err(napi::Error::from_reason("TPM sealing is only supported on Linux"))
+        }
+    }
+
+    /// Stop sealing the OS key part and remove the sealed key part.
+    #[napi]
+    pub async fn disable_tpm_sealing() -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        desktop_core::biometric::tpm::disable().map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        Ok(())
+    }
+
+    #[napi]
+    pub async fn is_tpm_available() -> napi::Result<bool> {
+        #[cfg(target_os = "linux")]
+        return Ok(desktop_core::biometric::tpm::is_available());
+        #[cfg(not(target_os = "linux"))]
+        return Ok(false);
+    }
+
+    /// Wrap the OS key part with the key `key_id` on the PKCS#11 token with `token_serial`, using
+    /// the PKCS#11 `module`. Takes precedence over TPM sealing. Only supported on Linux.
+    #[napi]
+    pub async fn enable_pkcs11(
+        module: String,
+        token_serial: String,
+        key_id: napi::bindgen_prelude::Buffer,
+    ) -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            desktop_core::biometric::pkcs11::enable(desktop_core::biometric::pkcs11::Pkcs11Config {
+                module: module.into(),
+                token_serial,
+                key_id: key_id.into(),
+            })
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (module, token_serial, key_id);
+            Err(napi::Error::from_reason("PKCS#11 unlock is only supported on Linux"))
+        }
+    }
+
+    #[napi]
+    pub async fn disable_pkcs11() -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        desktop_core::biometric::pkcs11::disable().map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Log in to the configured PKCS#11 token before deriving the key material. Pass no PIN for
+    /// readers with a PIN pad.
+    #[napi]
+    pub async fn pkcs11_login(pin: Option<String>) -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            desktop_core::biometric::pkcs11::login(pin.as_deref())
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = pin;
+            Err(napi::Error::from_reason("PKCS#11 unlock is only supported on Linux"))
+        }
+    }
+
+    #[napi]
+    pub async fn list_pkcs11_tokens(module: String) -> napi::Result<Vec<Pkcs11Token>> {
+        #[cfg(target_os = "linux")]
+        {
+            desktop_core::biometric::pkcs11::list_tokens(std::path::Path::new(&module))
+                .map(|tokens| tokens.into_iter().map(|t| t.into()).collect())
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = module;
+            Ok(Vec::new())
+        }
+    }
+
+    /// List the RSA and EC keys on the token, no login is required.
+    #[napi]
+    pub async fn list_pkcs11_keys(
+        module: String,
+        token_serial: String,
+    ) -> napi::Result<Vec<Pkcs11Key>> {
+        #[cfg(target_os = "linux")]
+        {
+            desktop_core::biometric::pkcs11::list_keys(std::path::Path::new(&module), &token_serial)
+                .map(|keys| keys.into_iter().map(|k| k.into()).collect())
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (module, token_serial);
+            Ok(Vec::new())
+        }
+    }
+
+    /// Cache keys derived by `getBiometricSecret` in the session keyring for `timeout_seconds`,
+    /// so later calls can omit the key material. `null` disables the cache and revokes all cached
+    /// keys. Only supported on Linux.
+    #[napi]
+    pub async fn set_key_cache_timeout(timeout_seconds: Option<u32>) -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            let timeout = timeout_seconds.map(|s| std::time::Duration::from_secs(s.into()));
+            desktop_core::biometric::key_cache::set_timeout(timeout)
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = timeout_seconds;
+            Err(napi::Error::from_reason("The key cache is only supported on Linux"))
+        }
+    }
+
+    /// Check if a cached key for the biometric secret exists, in which case the prompt can be skipped.
+    #[napi]
+    pub async fn has_cached_key(service: String, account: String) -> napi::Result<bool> {
+        #[cfg(target_os = "linux")]
+        return desktop_core::biometric::key_cache::load(&service, &account)
+            .map(|key| key.is_some())
+            .map_err(|e| napi::Error::from_reason(e.to_string()));
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (service, account);
+            Ok(false)
+        }
+    }
+
+    /// Revoke all cached keys. Should be called when the vault is locked.
+    #[napi]
+    pub async fn revoke_cached_keys() -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        return desktop_core::biometric::key_cache::revoke_all()
+            .map_err(|e| napi::Error::from_reason(e.to_string()));
+        #[cfg(not(target_os = "linux"))]
+        Ok(())
+    }
+
     #[napi(object)]
     pub struct KeyMaterial {
         pub os_key_part_b64: String,
@@ -118,6 +334,59 @@ pub mod biometrics {
         }
     }
 
+    #[napi(object)]
+    pub struct Pkcs11Token {
+        pub label: String,
+        pub manufacturer: String,
+        pub model: String,
+        pub serial: String,
+        /// The reader has a PIN pad, call `pkcs11Login` without a PIN.
+        pub protected_authentication_path: bool,
+    }
+
+    #[cfg(target_os = "linux")]
+    impl From<desktop_core::biometric::pkcs11::TokenInfo> for Pkcs11Token {
+        fn from(token: desktop_core::biometric::pkcs11::TokenInfo) -> Self {
+            Pkcs11Token {
+                label: token.label,
+                manufacturer: token.manufacturer,
+                model: token.model,
+                serial: token.serial,
+                protected_authentication_path: token.protected_authentication_path,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct Pkcs11Key {
+        pub id: napi::bindgen_prelude::Buffer,
+        pub label: String,
+        /// `rsa` or `ec`
+        pub key_type: String,
+    }
+
+    #[cfg(target_os = "linux")]
+    impl From<desktop_core::biometric::pkcs11::KeyInfo> for Pkcs11Key {
+        fn from(key: desktop_core::biometric::pkcs11::KeyInfo) -> Self {
+            Pkcs11Key {
+                id: key.id.into(),
+                label: key.label,
+                key_type: match key.key_type {
+                    desktop_core::biometric::pkcs11::KeyType::Rsa => "rsa".to_owned(),
+                    desktop_core::biometric::pkcs11::KeyType::Ec => "ec".to_owned(),
+                },
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct VerifyProgress {
+        /// e.g. `waiting` or `retry-scan`
+        pub status: String,
+        /// The message passed to `prompt`, to show while the reader waits for a finger.
+        pub message: String,
+    }
+
     #[napi(object)]
     pub struct OsDerivedKey {
         pub key_b64: String,
@@ -134,18 +403,260 @@ pub mod biometrics {
     }
 }
 
+#[napi]
+pub mod pins {
+    /// Protect the base64 encoded user key with a key derived from the PIN. The protected key is
+    /// deleted after `max_attempts` failed unlocks.
+    #[napi]
+    pub async fn set_pin(
+        service: String,
+        account: String,
+        pin: String,
+        email: String,
+        kdf: KdfConfig,
+        user_key_b64: String,
+        max_attempts: u32,
+    ) -> napi::Result<()> {
+        desktop_core::pin::set_pin(
+            &service,
+            &account,
+            &pin,
+            &email,
+            &kdf.try_into()?,
+            &user_key_b64,
+            max_attempts,
+        )
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Decrypt the user key protected with the PIN, returning it base64 encoded.
+    #[napi]
+    pub async fn unlock_with_pin(
+        service: String,
+        account: String,
+        pin: String,
+        email: String,
+        kdf: KdfConfig,
+    ) -> napi::Result<String> {
+        let user_key_b64 =
+            desktop_core::pin::unlock_with_pin(&service, &account, &pin, &email, &kdf.try_into()?)
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        // The JS string cannot be zeroed, so the copy is only made here
+        user_key_b64
+            .as_str()
+            .map(str::to_owned)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub async fn has_pin(service: String, account: String) -> napi::Result<bool> {
+        desktop_core::pin::has_pin(&service, &account)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Number of unlock attempts left before the protected key is deleted.
+    #[napi]
+    pub async fn remaining_pin_attempts(service: String, account: String) -> napi::Result<u32> {
+        desktop_core::pin::remaining_attempts(&service, &account)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub async fn remove_pin(service: String, account: String) -> napi::Result<()> {
+        desktop_core::pin::remove_pin(&service, &account)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// The account KDF settings. `kdf_type` is 0 for PBKDF2 and 1 for Argon2id, `memory` is in
+    /// MiB.
+    #[napi(object)]
+    pub struct KdfConfig {
+        pub kdf_type: u32,
+        pub iterations: u32,
+        pub memory: Option<u32>,
+        pub parallelism: Option<u32>,
+    }
+
+    impl TryFrom<KdfConfig> for desktop_core::pin::Kdf {
+        type Error = napi::Error;
+
+        fn try_from(kdf: KdfConfig) -> napi::Result<Self> {
+            match (kdf.kdf_type, kdf.memory, kdf.parallelism) {
+                (0, _, _) => Ok(desktop_core::pin::Kdf::Pbkdf2 {
+                    iterations: kdf.iterations,
+                }),
+                (1, Some(memory), Some(parallelism)) => Ok(desktop_core::pin::Kdf::Argon2id {
+                    iterations: kdf.iterations,
+                    memory,
+                    parallelism,
+                }),
+                (1, _, _) => Err(napi::Error::from_reason(
+                    "Argon2id requires memory and parallelism",
+                )),
+                (kdf_type, _, _) => Err(napi::Error::from_reason(format!(
+                    "Unknown KDF type {}",
+                    kdf_type
+                ))),
+            }
+        }
+    }
+}
+
 #[napi]
 pub mod clipboards {
+    use napi::{
+        threadsafe_function::{
+            ErrorStrategy::CalleeHandled, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+        },
+        tokio,
+    };
+
+    /// `target` is one of `clipboard` (the default), `primary` for the selection pasted with
+    /// middle-click on Linux, or `both`.
+    #[napi]
+    pub async fn read(target: Option<String>) -> napi::Result<String> {
+        desktop_core::clipboard::read(parse_target(target)?)
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Write to the clipboard. With `clear_after_ms`, the clipboard is cleared after the timeout
+    /// unless the user copied something else, and the id of the pending clear is returned.
     #[napi]
-    pub async fn read() -> napi::Result<String> {
-        desktop_core::clipboard::read().map_err(|e| napi::Error::from_reason(e.to_string()))
+    pub async fn write(
+        text: String,
+        password: bool,
+        clear_after_ms: Option<u32>,
+        target: Option<String>,
+    ) -> napi::Result<Option<u32>> {
+        let clear_after = clear_after_ms.map(|ms| std::time::Duration::from_millis(ms.into()));
+        desktop_core::clipboard::write(&text, password, clear_after, parse_target(target)?)
+            .await
+            .map(|handle| handle.map(|h| h.id()))
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
 
     #[napi]
-    pub async fn write(text: String, password: bool) -> napi::Result<()> {
-        desktop_core::clipboard::write(&text, password)
+    pub async fn clear(target: Option<String>) -> napi::Result<()> {
+        desktop_core::clipboard::clear(parse_target(target)?)
+            .await
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
+
+    /// Call `callback` whenever the clipboard of `target` changes, until the returned
+    /// subscription is stopped. With `content`, the new text is included in the event.
+    #[napi]
+    pub async fn watch(
+        callback: ThreadsafeFunction<ClipboardChange, CalleeHandled>,
+        target: Option<String>,
+        content: bool,
+    ) -> napi::Result<ClipboardSubscription> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let subscription = desktop_core::clipboard::watch(tx, parse_target(target)?, content)
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        // Aborted on stop, which releases the callback right away
+        let task = tokio::spawn(async move {
+            while let Some(change) = rx.recv().await {
+                callback.call(Ok(change.into()), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        });
+        Ok(ClipboardSubscription {
+            inner: subscription,
+            task,
+        })
+    }
+
+    #[napi]
+    pub struct ClipboardSubscription {
+        inner: desktop_core::clipboard::ClipboardSubscription,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    #[napi]
+    impl ClipboardSubscription {
+        /// Stop listening. Calling it again has no effect.
+        #[napi]
+        pub fn stop(&self) {
+            self.inner.stop();
+            self.task.abort();
+        }
+
+        #[napi(getter)]
+        pub fn stopped(&self) -> bool {
+            self.inner.is_stopped()
+        }
+    }
+
+    #[napi(object)]
+    pub struct ClipboardChange {
+        /// `clipboard` or `primary`
+        pub target: String,
+        pub text: Option<String>,
+    }
+
+    impl From<desktop_core::clipboard::ClipboardChange> for ClipboardChange {
+        fn from(change: desktop_core::clipboard::ClipboardChange) -> Self {
+            ClipboardChange {
+                target: change.target.as_str().to_owned(),
+                text: change.text,
+            }
+        }
+    }
+
+    fn parse_target(target: Option<String>) -> napi::Result<desktop_core::clipboard::Target> {
+        target
+            .map_or(Ok(Default::default()), |target| target.parse())
+            .map_err(|e: anyhow::Error| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Cancel a pending clear. Returns `false` if it already ran.
+    #[napi]
+    pub async fn cancel_clear(id: u32) -> napi::Result<bool> {
+        Ok(desktop_core::clipboard::clear_handle(id).is_some_and(|handle| handle.cancel()))
+    }
+
+    /// One of `pending`, `cleared`, `replaced`, `cancelled` or `failed`.
+    #[napi]
+    pub async fn clear_status(id: u32) -> napi::Result<String> {
+        desktop_core::clipboard::clear_handle(id)
+            .map(|handle| handle.status().as_str().to_owned())
+            .ok_or_else(|| napi::Error::from_reason(format!("No clipboard clear with id {}", id)))
+    }
+
+    /// Offer `text` on the clipboard until it has been pasted once, then clear the clipboard.
+    /// The callback receives `pasted`, `replaced`, `cancelled` or `failed` when the offer ends.
+    /// Only supported on Linux.
+    #[napi]
+    pub async fn write_paste_once(
+        text: String,
+        callback: ThreadsafeFunction<String, CalleeHandled>,
+    ) -> napi::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            desktop_core::clipboard::write_paste_once(&text, move |result| {
+                callback.call(
+                    Ok(result.as_str().to_owned()),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            })
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (text, callback);
+            Err(napi::Error::from_reason("Paste-once is only supported on Linux"))
+        }
+    }
+
+    /// Withdraw the pending paste-once offer. Returns `false` if there was none.
+    #[napi]
+    pub async fn cancel_paste_once() -> napi::Result<bool> {
+        #[cfg(target_os = "linux")]
+        return Ok(desktop_core::clipboard::cancel_paste_once());
+        #[cfg(not(target_os = "linux"))]
+        return Ok(false);
+    }
 }
 
 #[napi]
@@ -165,27 +676,173 @@ pub mod processisolations {
         desktop_core::process_isolation::disable_memory_access()
             .map_err(|e| napi::Error::from_reason(e.to_string()))
     }
+    /// Report whether the process memory can be read by other processes, e.g. because a debugger
+    /// is attached. Reports on the process `pid` of the same user if given, e.g. a renderer.
+    #[napi]
+    pub async fn status(pid: Option<u32>) -> napi::Result<IsolationStatus> {
+        desktop_core::process_isolation::status(pid)
+            .map(|status| status.into())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi(object)]
+    pub struct IsolationStatus {
+        /// False once `disableMemoryAccess` has taken effect.
+        pub dumpable: bool,
+        /// The soft core dump size limit in bytes, unset if unlimited.
+        pub core_limit_soft: Option<i64>,
+        /// The hard core dump size limit in bytes, unset if unlimited.
+        pub core_limit_hard: Option<i64>,
+        /// The Yama ptrace scope, unset if Yama is not enabled.
+        pub ptrace_scope: Option<u32>,
+        pub core_pattern: String,
+        /// Core dumps are piped to a handler like systemd-coredump.
+        pub core_pattern_piped: bool,
+        /// The process tracing this one, e.g. a debugger.
+        pub tracer_pid: Option<u32>,
+    }
+
+    impl From<desktop_core::process_isolation::IsolationStatus> for IsolationStatus {
+        fn from(status: desktop_core::process_isolation::IsolationStatus) -> Self {
+            IsolationStatus {
+                dumpable: status.dumpable,
+                core_limit_soft: status.core_limit.soft.map(|limit| limit as i64),
+                core_limit_hard: status.core_limit.hard.map(|limit| limit as i64),
+                ptrace_scope: status.ptrace_scope,
+                core_pattern_piped: status.is_core_pattern_piped(),
+                core_pattern: status.core_pattern,
+                tracer_pid: status.tracer_pid,
+            }
+        }
+    }
 }
 
 #[napi]
 pub mod powermonitors {
     use napi::{threadsafe_function::{ErrorStrategy::CalleeHandled, ThreadsafeFunction, ThreadsafeFunctionCallMode}, tokio};
 
+    /// Call `callback` whenever the screen is locked or unlocked, the system suspends or resumes
+    /// or the session becomes inactive or ends, until the returned subscription is stopped.
     #[napi]
-    pub async fn on_lock(callback: ThreadsafeFunction<(), CalleeHandled>) -> napi::Result<()> {
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(32);
-        desktop_core::powermonitor::on_lock(tx).await.map_err(|e| napi::Error::from_reason(e.to_string()))?;
+    pub async fn on_lock(callback: ThreadsafeFunction<LockEvent, CalleeHandled>) -> napi::Result<LockSubscription> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let subscription = desktop_core::powermonitor::on_lock(tx).await.map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        // Ends once the subscription is stopped, which releases the callback
         tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                callback.call(Ok(message.into()), ThreadsafeFunctionCallMode::NonBlocking);
+            while let Some(event) = rx.recv().await {
+                callback.call(Ok(event.into()), ThreadsafeFunctionCallMode::NonBlocking);
             }
         });
-        Ok(())
+        Ok(LockSubscription { inner: subscription })
+    }
+
+    #[napi]
+    pub struct LockSubscription {
+        inner: desktop_core::powermonitor::LockSubscription,
     }
 
     #[napi]
-    pub async fn is_lock_monitor_available() -> napi::Result<bool> {
-        Ok(desktop_core::powermonitor::is_lock_monitor_available().await)
+    impl LockSubscription {
+        /// Stop listening. Calling it again has no effect.
+        #[napi]
+        pub fn stop(&self) {
+            self.inner.stop();
+        }
+
+        #[napi(getter)]
+        pub fn stopped(&self) -> bool {
+            self.inner.is_stopped()
+        }
+
+        /// Let the system sleep once a `suspend` event has been handled. Sleep is delayed for a
+        /// few seconds at most otherwise.
+        #[napi]
+        pub fn acknowledge_suspend(&self) {
+            self.inner.acknowledge_suspend();
+        }
     }
 
+    #[napi(object)]
+    pub struct LockEvent {
+        /// `locked`, `unlocked`, `suspend`, `resume`, `session-inactive` or `session-ending`
+        pub kind: String,
+        /// The D-Bus interface that reported the change, e.g. `org.gnome.ScreenSaver`.
+        pub source: String,
+    }
+
+    impl From<desktop_core::powermonitor::LockEvent> for LockEvent {
+        fn from(event: desktop_core::powermonitor::LockEvent) -> Self {
+            LockEvent {
+                kind: event.kind().to_owned(),
+                source: event.source().to_owned(),
+            }
+        }
+    }
+
+    /// Call `callback` when the user has been idle for `threshold_seconds` and when they are
+    /// active again, until the returned subscription is stopped. If the monitor fails, the
+    /// callback receives the error and the subscription stops.
+    #[napi]
+    pub async fn on_idle(threshold_seconds: u32, callback: ThreadsafeFunction<IdleEvent, CalleeHandled>) -> napi::Result<IdleSubscription> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+        let threshold = std::time::Duration::from_secs(threshold_seconds.into());
+        let subscription = desktop_core::powermonitor::on_idle(threshold, tx).await.map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                callback.call(event.map(Into::into).map_err(napi::Error::from_reason), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        });
+        Ok(IdleSubscription { inner: subscription })
+    }
+
+    #[napi]
+    pub struct IdleSubscription {
+        inner: desktop_core::powermonitor::IdleSubscription,
+    }
+
+    #[napi]
+    impl IdleSubscription {
+        /// Stop listening. Calling it again has no effect.
+        #[napi]
+        pub fn stop(&self) {
+            self.inner.stop();
+        }
+
+        #[napi(getter)]
+        pub fn stopped(&self) -> bool {
+            self.inner.is_stopped()
+        }
+    }
+
+    #[napi(object)]
+    pub struct IdleEvent {
+        /// `idle` or `active`
+        pub kind: String,
+    }
+
+    impl From<desktop_core::powermonitor::IdleEvent> for IdleEvent {
+        fn from(event: desktop_core::powermonitor::IdleEvent) -> Self {
+            IdleEvent {
+                kind: event.kind().to_owned(),
+            }
+        }
+    }
+
+    /// Also watch the screen saver `interface` at `path`, served under the name of the
+    /// interface. Applies to subscriptions and checks started afterwards.
+    #[napi]
+    pub fn add_screen_lock_monitor(interface: String, path: String) -> napi::Result<()> {
+        desktop_core::powermonitor::add_screen_lock_monitor(&interface, &path).map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// The D-Bus services that report the lock, empty if it cannot be monitored.
+    #[napi]
+    pub async fn is_lock_monitor_available() -> napi::Result<Vec<String>> {
+        desktop_core::powermonitor::is_lock_monitor_available().await.map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub async fn is_locked() -> napi::Result<bool> {
+        desktop_core::powermonitor::is_locked().await.map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
 }
@@ -191,6 +191,13 @@ export class AppComponent implements OnInit, OnDestroy {
             // eslint-disable-next-line @typescript-eslint/no-floating-promises
             this.updateAppMenu();
             this.systemService.cancelProcessReload();
+            if (await ipc.platform.processisolation.isDebuggerAttached()) {
+              this.platformUtilsService.showToast(
+                "warning",
+                this.i18nService.t("warning"),
+                this.i18nService.t("debuggerAttachedDesc"),
+              );
+            }
             break;
           case "loggedOut":
             this.modalService.closeAll();
@@ -439,10 +446,16 @@ export class AppComponent implements OnInit, OnDestroy {
           }
           case "systemSuspended":
             await this.checkForSystemTimeout(VaultTimeoutStringType.OnSleep);
+            // The system waits for the vault to be locked before it sleeps
+            this.messagingService.send("systemSuspendHandled");
             break;
           case "systemLocked":
             await this.checkForSystemTimeout(VaultTimeoutStringType.OnLocked);
             break;
+          case "systemSessionEnded":
+            // Switching users or logging out leaves the vault behind, whatever the timeout
+            await this.timeOutAccounts((timeout) => timeout !== VaultTimeoutStringType.Never);
+            break;
           case "systemIdle":
             await this.checkForSystemTimeout(VaultTimeoutStringType.OnIdle);
             break;
@@ -767,13 +780,18 @@ export class AppComponent implements OnInit, OnDestroy {
   }
 
   private async checkForSystemTimeout(timeout: VaultTimeout): Promise<void> {
+    await this.timeOutAccounts((accountTimeout) => accountTimeout === timeout);
+  }
+
+  // Lock or log out the accounts whose vault timeout matches
+  private async timeOutAccounts(matches: (timeout: VaultTimeout) => boolean): Promise<void> {
     const accounts = await firstValueFrom(this.accountService.accounts$);
     for (const userId in accounts) {
       if (userId == null) {
         continue;
       }
       const options = await this.getVaultTimeoutOptions(userId);
-      if (options[0] === timeout) {
+      if (matches(options[0])) {
         // FIXME: Verify that this floating promise is intentional. If it is, add an explanatory comment and ensure there is proper error handling.
         // eslint-disable-next-line @typescript-eslint/no-floating-promises
         options[1] === "logOut"
@@ -1,6 +1,7 @@
 import * as fs from "fs";
 import * as path from "path";
 
+import { biometrics } from "@bitwarden/desktop-napi";
 import { app, ipcMain } from "electron";
 import { firstValueFrom } from "rxjs";
 
@@ -77,6 +78,19 @@ export class MessagingMain {
       case "getWindowIsFocused":
         this.windowIsFocused();
         break;
+      case "systemSuspendHandled":
+        this.main.powerMonitorMain.acknowledgeSuspend();
+        break;
+      case "locked":
+        // Biometric keys cached in the session keyring must not outlive the unlocked vault
+        if (process.platform === "linux") {
+          try {
+            await biometrics.revokeCachedKeys();
+          } catch (e) {
+            this.main.logService.error("Failed to revoke cached biometric keys", e);
+          }
+        }
+        break;
       default:
         break;
     }
@@ -12,6 +12,7 @@ const IdleCheckInterval = 30 * 1000; // 30 seconds
 
 export class PowerMonitorMain {
   private idle = false;
+  private lockSubscription: powermonitors.LockSubscription | null = null;
 
   constructor(
     private messagingService: MessageSender,
@@ -19,37 +20,104 @@ export class PowerMonitorMain {
   ) {}
 
   init() {
-    // ref: https://github.com/electron/electron/issues/13767
-    if (!isSnapStore()) {
-      // System sleep
-      powerMonitor.on("suspend", () => {
-        this.messagingService.send("systemSuspended");
-      });
-    }
-
     if (process.platform !== "linux") {
+      this.listenForElectronSuspend();
+
       // System locked
       powerMonitor.on("lock-screen", () => {
         this.messagingService.send("systemLocked");
       });
     } else {
+      // Screen savers and logind, which also reports suspend where Electron cannot
+      // ref: https://github.com/electron/electron/issues/13767
       powermonitors
-        .onLock(() => {
-          this.messagingService.send("systemLocked");
+        .onLock((error, event) => {
+          if (error != null) {
+            return;
+          }
+          if (event.kind === "locked") {
+            this.messagingService.send("systemLocked");
+          } else if (event.kind === "session-inactive" || event.kind === "session-ending") {
+            this.messagingService.send("systemSessionEnded");
+          } else if (event.kind === "suspend") {
+            this.messagingService.send("systemSuspended");
+          }
+        })
+        .then((subscription) => {
+          this.lockSubscription = subscription;
         })
         .catch((error) => {
           this.logService.error("Error setting up lock monitor", { error });
         });
+      void this.listenForSuspendWithoutLogind();
     }
     ipcMain.handle("powermonitor.isLockMonitorAvailable", async (_event: any, _message: any) => {
       if (process.platform !== "linux") {
         return true;
       } else {
-        return await powermonitors.isLockMonitorAvailable();
+        try {
+          const monitors = await powermonitors.isLockMonitorAvailable();
+          this.logService.debug("Lock monitors found", monitors);
+          return monitors.length > 0;
+        } catch (error) {
+          this.logService.error("Error checking the lock monitor", { error });
+          return false;
+        }
       }
     });
 
     // System idle
+    if (process.platform === "linux") {
+      powermonitors
+        .onIdle(IdleLockSeconds, (error, event) => {
+          if (error != null) {
+            // The native monitor stopped, fall back to Electron's idle time
+            this.logService.error("Idle monitor failed", { error });
+            this.pollIdle();
+          } else if (event.kind === "idle") {
+            this.messagingService.send("systemIdle");
+          }
+        })
+        .catch((error) => {
+          this.logService.error("Error setting up idle monitor", { error });
+          this.pollIdle();
+        });
+    } else {
+      this.pollIdle();
+    }
+  }
+
+  /** Let a suspend delayed by the lock monitor proceed. */
+  acknowledgeSuspend() {
+    this.lockSubscription?.acknowledgeSuspend();
+  }
+
+  /** Fall back to Electron's suspend event where logind cannot report suspend. */
+  private async listenForSuspendWithoutLogind() {
+    try {
+      const monitors = await powermonitors.isLockMonitorAvailable();
+      if (monitors.includes("org.freedesktop.login1")) {
+        return;
+      }
+    } catch (error) {
+      this.logService.error("Error checking the lock monitor", { error });
+    }
+    this.listenForElectronSuspend();
+  }
+
+  private listenForElectronSuspend() {
+    // ref: https://github.com/electron/electron/issues/13767
+    if (isSnapStore()) {
+      return;
+    }
+
+    // System sleep
+    powerMonitor.on("suspend", () => {
+      this.messagingService.send("systemSuspended");
+    });
+  }
+
+  private pollIdle() {
     global.setInterval(() => {
       const idleSeconds: number = powerMonitor.getSystemIdleTime();
       const idle = idleSeconds >= IdleLockSeconds;
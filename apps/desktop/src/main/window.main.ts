@@ -48,6 +48,30 @@ export class WindowMain {
   ) {}
 
   init(): Promise<any> {
+    ipcMain.handle("processisolation.isDebuggerAttached", async () => {
+      if (!isLinux()) {
+        return false;
+      }
+      try {
+        // A debugger can attach to the renderer as well as to the main process, which is the
+        // default when no pid is passed
+        const pids: (number | undefined)[] = [undefined];
+        if (this.win != null) {
+          pids.push(this.win.webContents.getOSProcessId());
+        }
+        for (const pid of pids) {
+          const status = await processisolations.status(pid);
+          if (status.tracerPid != null) {
+            return true;
+          }
+        }
+        return false;
+      } catch (e) {
+        this.logService.error("Failed to get process isolation status", e);
+        return false;
+      }
+    });
+
     // Perform a hard reload of the render process by crashing it. This is suboptimal but ensures that all memory gets
     // cleared, as the process itself will be completely garbage collected.
     ipcMain.on("reload-process", async () => {
@@ -132,6 +156,13 @@ export class WindowMain {
                 this.logService.error("Failed to disable memory dumps", e);
               }
             }
+
+            try {
+              const status = await processisolations.status();
+              this.logService.debug("Process isolation status", status);
+            } catch (e) {
+              this.logService.error("Failed to get process isolation status", e);
+            }
           }
 
           await this.createWindow();
@@ -76,6 +76,11 @@ const powermonitor = {
     ipcRenderer.invoke("powermonitor.isLockMonitorAvailable"),
 };
 
+const processisolation = {
+  isDebuggerAttached: (): Promise<boolean> =>
+    ipcRenderer.invoke("processisolation.isDebuggerAttached"),
+};
+
 const nativeMessaging = {
   sendReply: (message: EncryptedMessageResponse | UnencryptedMessageResponse) => {
     ipcRenderer.send("nativeMessagingReply", message);
@@ -183,6 +188,7 @@ export default {
   biometric,
   clipboard,
   powermonitor,
+  processisolation,
   nativeMessaging,
   crypto,
   ephemeralStore,